@@ -0,0 +1,99 @@
+use crate::keycodes::{KeyCodes, ModifierMasks};
+
+// what a keymap cell does while its key is held
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Key(KeyCodes),
+    // keycode sent together with its own modifier bits. with Reserved as the
+    // keycode the modifiers are simply held, like a modifier key.
+    ModKey(u8, KeyCodes),
+    Mouse(MouseAction),
+    // shift the letters of the next word
+    CapsWord,
+    // holds the first modifiers, tapped alone it sends the keycode with the
+    // second modifiers
+    SpaceCadet(u8, u8, KeyCodes),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MouseAction {
+    MoveLeft,
+    MoveDown,
+    MoveUp,
+    MoveRight,
+    LeftButton,
+    RightButton,
+}
+
+pub const fn k(code: KeyCodes) -> Action {
+    Action::Key(code)
+}
+
+const CTRL: u8 = ModifierMasks::LeftCtrl as u8;
+const SHIFT: u8 = ModifierMasks::LeftShift as u8;
+const ALT: u8 = ModifierMasks::LeftAlt as u8;
+const GUI: u8 = ModifierMasks::LeftGui as u8;
+
+#[allow(non_snake_case)]
+pub const fn LCTL(code: KeyCodes) -> Action {
+    Action::ModKey(CTRL, code)
+}
+
+#[allow(non_snake_case)]
+pub const fn LSFT(code: KeyCodes) -> Action {
+    Action::ModKey(SHIFT, code)
+}
+
+#[allow(non_snake_case)]
+pub const fn LALT(code: KeyCodes) -> Action {
+    Action::ModKey(ALT, code)
+}
+
+#[allow(non_snake_case)]
+pub const fn LGUI(code: KeyCodes) -> Action {
+    Action::ModKey(GUI, code)
+}
+
+#[allow(non_snake_case)]
+pub const fn RCTL(code: KeyCodes) -> Action {
+    Action::ModKey(ModifierMasks::RightCtrl as u8, code)
+}
+
+#[allow(non_snake_case)]
+pub const fn RSFT(code: KeyCodes) -> Action {
+    Action::ModKey(ModifierMasks::RightShift as u8, code)
+}
+
+#[allow(non_snake_case)]
+pub const fn RALT(code: KeyCodes) -> Action {
+    Action::ModKey(ModifierMasks::RightAlt as u8, code)
+}
+
+#[allow(non_snake_case)]
+pub const fn RGUI(code: KeyCodes) -> Action {
+    Action::ModKey(ModifierMasks::RightGui as u8, code)
+}
+
+// ctrl+shift+alt+gui, HYPR(Reserved) is a plain hyper key
+#[allow(non_snake_case)]
+pub const fn HYPR(code: KeyCodes) -> Action {
+    Action::ModKey(CTRL | SHIFT | ALT | GUI, code)
+}
+
+// ctrl+shift+alt, MEH(Reserved) is a plain meh key
+#[allow(non_snake_case)]
+pub const fn MEH(code: KeyCodes) -> Action {
+    Action::ModKey(CTRL | SHIFT | ALT, code)
+}
+
+// space cadet keys: shift, ctrl or alt while held, tapped alone the left ones
+// send ( and the right ones )
+pub const SC_LSPO: Action = Action::SpaceCadet(SHIFT, SHIFT, KeyCodes::Key9);
+pub const SC_RSPC: Action =
+    Action::SpaceCadet(ModifierMasks::RightShift as u8, SHIFT, KeyCodes::Key0);
+pub const SC_LCPO: Action = Action::SpaceCadet(CTRL, SHIFT, KeyCodes::Key9);
+pub const SC_RCPC: Action =
+    Action::SpaceCadet(ModifierMasks::RightCtrl as u8, SHIFT, KeyCodes::Key0);
+pub const SC_LAPO: Action = Action::SpaceCadet(ALT, SHIFT, KeyCodes::Key9);
+pub const SC_RAPC: Action =
+    Action::SpaceCadet(ModifierMasks::RightAlt as u8, SHIFT, KeyCodes::Key0);
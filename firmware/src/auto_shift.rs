@@ -0,0 +1,277 @@
+use crate::{
+    action::{Action, LSFT},
+    clock::{Duration, Instant},
+    keycodes::KeyCodes::{self, *},
+    pipeline::{ActionEvent, ActionStage, DeferredRelease},
+};
+
+// how long a key of each class has to be held to come out shifted, None
+// leaves the class alone
+#[derive(Clone, Copy)]
+pub struct AutoShiftTimeouts {
+    pub letters: Option<Duration>,
+    pub numbers: Option<Duration>,
+    pub symbols: Option<Duration>,
+}
+
+#[derive(Clone, Copy)]
+enum KeyClass {
+    Letter,
+    Number,
+    Symbol,
+}
+
+const KEY_CLASSES: [(KeyCodes, KeyCodes, KeyClass); 3] = [
+    (KeyA, KeyZ, KeyClass::Letter),
+    (Key1, Key0, KeyClass::Number),
+    (Minus, Slash, KeyClass::Symbol),
+];
+
+// holding a key past its timeout sends it shifted, tapping it sends it as is.
+// while a modifier key is held keys go through untouched, so shortcuts and
+// shift itself work as usual.
+pub struct AutoShift {
+    timeouts: AutoShiftTimeouts,
+    // key held back until it is known whether it is tapped or held
+    pending: Option<(ActionEvent, Duration)>,
+    release: DeferredRelease,
+    mods_held: u8,
+}
+
+impl AutoShift {
+    pub fn new(timeouts: AutoShiftTimeouts) -> Self {
+        AutoShift {
+            timeouts,
+            pending: None,
+            release: DeferredRelease::new(),
+            mods_held: 0,
+        }
+    }
+
+    fn timeout(&self, action: Action) -> Option<Duration> {
+        let Action::Key(code) = action else {
+            return None;
+        };
+        return match code.find_range(&KEY_CLASSES)? {
+            KeyClass::Letter => self.timeouts.letters,
+            KeyClass::Number => self.timeouts.numbers,
+            KeyClass::Symbol => self.timeouts.symbols,
+        };
+    }
+}
+
+impl ActionStage for AutoShift {
+    fn process(&mut self, event: ActionEvent, emit: &mut impl FnMut(ActionEvent)) {
+        self.release.flush(event.key.time, emit);
+        let modifier = match event.action {
+            Action::Key(code) => code.modifier_mask().is_some(),
+            Action::ModKey(_, KeyCodes::Reserved) => true,
+            _ => false,
+        };
+        if modifier {
+            if event.key.pressed {
+                self.mods_held += 1;
+            } else {
+                self.mods_held = self.mods_held.saturating_sub(1);
+            }
+        }
+
+        if event.key.pressed {
+            // typing on while a key is held back decides it was a tap
+            if let Some((pending, _)) = self.pending.take() {
+                emit(pending);
+            }
+            if self.mods_held == 0 {
+                if let Some(timeout) = self.timeout(event.action) {
+                    self.pending = Some((event, timeout));
+                    return;
+                }
+            }
+        } else if let Some((pending, _)) = self.pending {
+            if pending.key.pos == event.key.pos {
+                self.pending = None;
+                emit(pending);
+                self.release.defer(event, emit);
+                return;
+            }
+        }
+        emit(event);
+    }
+
+    fn tick(&mut self, now: Instant, emit: &mut impl FnMut(ActionEvent)) {
+        self.release.flush(now, emit);
+        if let Some((pending, timeout)) = self.pending {
+            if now - pending.key.time >= timeout {
+                self.pending = None;
+                if let Action::Key(code) = pending.action {
+                    emit(ActionEvent {
+                        action: LSFT(code),
+                        ..pending
+                    });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        board::{COLS, ROWS},
+        clock::{Clock, FakeClock},
+        held::HeldKeys,
+        layout::{pos, KeyPos, FN_KEY_POS, KEY_LAYOUT, KEY_LAYOUT_WITH_FN},
+        pipeline::{ActionProcessor, KeymapStage},
+        report::Reports,
+        sim::{at, event, key},
+    };
+
+    const HOLD: Duration = Duration::millis(175);
+
+    fn auto_shift() -> AutoShift {
+        return AutoShift::new(AutoShiftTimeouts {
+            letters: Some(HOLD),
+            numbers: Some(HOLD * 2),
+            symbols: None,
+        });
+    }
+
+    // holds `action` down, ticking every millisecond until `held` has passed
+    fn hold(
+        auto_shift: &mut AutoShift,
+        clock: &FakeClock,
+        action: Action,
+        held: Duration,
+    ) -> Vec<(Action, bool)> {
+        let mut events = Vec::new();
+        let mut emit = |event: ActionEvent| events.push((event.action, event.key.pressed));
+        auto_shift.process(event(pos(2, 1), true, action, clock.now()), &mut emit);
+        for _ in 0..held.to_millis() {
+            clock.advance(Duration::millis(1));
+            auto_shift.tick(clock.now(), &mut emit);
+        }
+        auto_shift.process(event(pos(2, 1), false, action, clock.now()), &mut emit);
+        clock.advance(Duration::millis(1));
+        auto_shift.tick(clock.now(), &mut emit);
+        return events;
+    }
+
+    #[test]
+    fn each_class_shifts_after_its_own_timeout() {
+        let clock = FakeClock::new();
+        let mut auto_shift = auto_shift();
+        let a = Action::Key(KeyA);
+        let expected = [(LSFT(KeyA), true), (a, false)];
+        assert_eq!(hold(&mut auto_shift, &clock, a, HOLD), expected);
+        let one = Action::Key(Key1);
+        let expected = [(one, true), (one, false)];
+        assert_eq!(
+            hold(&mut auto_shift, &clock, one, HOLD * 2 - Duration::millis(1)),
+            expected
+        );
+        let expected = [(LSFT(Key1), true), (one, false)];
+        assert_eq!(hold(&mut auto_shift, &clock, one, HOLD * 2), expected);
+        // symbols are left alone
+        let minus = Action::Key(Minus);
+        let expected = [(minus, true), (minus, false)];
+        assert_eq!(hold(&mut auto_shift, &clock, minus, HOLD * 4), expected);
+    }
+
+    #[test]
+    fn tap_goes_out_as_is() {
+        let clock = FakeClock::new();
+        let mut auto_shift = auto_shift();
+        let mut events = Vec::new();
+        let a = Action::Key(KeyA);
+        auto_shift.process(event(pos(2, 1), true, a, clock.now()), &mut |e| {
+            events.push(e)
+        });
+        clock.advance(Duration::millis(50));
+        auto_shift.tick(clock.now(), &mut |e| events.push(e));
+        assert!(events.is_empty());
+        auto_shift.process(event(pos(2, 1), false, a, clock.now()), &mut |e| {
+            events.push(e)
+        });
+        // the release waits a scan so the host sees the press first
+        assert_eq!(events, [event(pos(2, 1), true, a, at(0))]);
+        clock.advance(Duration::millis(1));
+        auto_shift.tick(clock.now(), &mut |e| events.push(e));
+        assert_eq!(events[1], event(pos(2, 1), false, a, at(50)));
+    }
+
+    #[test]
+    fn held_modifier_leaves_keys_alone() {
+        let clock = FakeClock::new();
+        let mut auto_shift = auto_shift();
+        let ctrl = Action::Key(LeftCtrl);
+        auto_shift.process(event(pos(2, 0), true, ctrl, clock.now()), &mut |_| {});
+        let a = Action::Key(KeyA);
+        assert_eq!(
+            hold(&mut auto_shift, &clock, a, HOLD * 2),
+            [(a, true), (a, false)]
+        );
+        auto_shift.process(event(pos(2, 0), false, ctrl, clock.now()), &mut |_| {});
+        assert_eq!(
+            hold(&mut auto_shift, &clock, a, HOLD),
+            [(LSFT(KeyA), true), (a, false)]
+        );
+    }
+
+    #[test]
+    fn fn_layer_keys_shift_by_their_own_class() {
+        let clock = FakeClock::new();
+        let mut keymap = HeldKeys::<ROWS, COLS>::new(&KEY_LAYOUT, &KEY_LAYOUT_WITH_FN);
+        let mut auto_shift = auto_shift();
+        let mut events = Vec::new();
+        let mut send = |pos: KeyPos, pressed: bool, time: Instant, events: &mut Vec<Action>| {
+            if let Some(event) = keymap.process(key(pos, pressed, time)) {
+                auto_shift.process(event, &mut |e| events.push(e.action));
+            }
+        };
+        send(FN_KEY_POS, true, clock.now(), &mut events);
+        // 1 is F1 on the fn layer, which no class covers
+        let one = KeyPos { row: 0, col: 1 };
+        send(one, true, clock.now(), &mut events);
+        assert_eq!(events, [Action::Key(F1)]);
+        send(one, false, clock.now(), &mut events);
+        // a letter pressed on the fn layer still shifts, even once fn is let go
+        let a = KeyPos { row: 2, col: 1 };
+        send(a, true, clock.now(), &mut events);
+        send(FN_KEY_POS, false, clock.now(), &mut events);
+        clock.advance(HOLD);
+        auto_shift.tick(clock.now(), &mut |e| events.push(e.action));
+        assert_eq!(events[2..], [LSFT(KeyA)]);
+    }
+
+    #[test]
+    fn shifted_key_in_rollover_does_not_type_the_other_key_again() {
+        let clock = FakeClock::new();
+        let mut auto_shift = auto_shift();
+        let mut reports = Reports::new(&[]);
+        let (a, b) = (Action::Key(KeyA), Action::Key(KeyB));
+        auto_shift.process(event(pos(2, 2), true, b, clock.now()), &mut |e| {
+            reports.process(e)
+        });
+        clock.advance(Duration::millis(30));
+        auto_shift.process(event(pos(2, 1), true, a, clock.now()), &mut |e| {
+            reports.process(e)
+        });
+        assert_eq!(
+            reports.keyboard_report().keycodes,
+            [KeyB as u8, 0, 0, 0, 0, 0]
+        );
+        clock.advance(HOLD);
+        auto_shift.tick(clock.now(), &mut |e| reports.process(e));
+        let report = reports.keyboard_report();
+        assert_eq!(report.modifier, 0x02);
+        assert_eq!(report.keycodes, [KeyA as u8, 0, 0, 0, 0, 0]);
+        auto_shift.process(event(pos(2, 1), false, a, clock.now()), &mut |e| {
+            reports.process(e)
+        });
+        // b is still down but was already typed
+        let report = reports.keyboard_report();
+        assert_eq!(report.modifier, 0);
+        assert_eq!(report.keycodes, [0; 6]);
+    }
+}
@@ -0,0 +1,46 @@
+#[allow(unused)]
+#[derive(PartialEq, Clone, Copy)]
+pub enum DiodeDirection {
+    Col2Row, // rows are driven low, pulled-up columns are read
+    Row2Col, // columns are driven low, pulled-up rows are read
+}
+
+// everything the scanner needs to know about a pcb revision
+pub trait Board<const ROWS: usize, const COLS: usize> {
+    const ROW_PINS: [u8; ROWS]; // gpio number of each row, row0 first
+    const COL_PINS: [u8; COLS]; // gpio number of each column, col0 first
+    const DIODES: DiodeDirection;
+    const SETTLE_NS: u32; // time the lines need after a drive change
+    const GHOST_FILTER: bool; // hold back ghost rectangles, for matrices without diodes
+}
+
+#[allow(unused)]
+pub struct WavierKeys;
+
+impl Board<5, 15> for WavierKeys {
+    const ROW_PINS: [u8; 5] = [20, 19, 18, 17, 16];
+    const COL_PINS: [u8; 15] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14];
+    const DIODES: DiodeDirection = DiodeDirection::Col2Row;
+    const SETTLE_NS: u32 = 80;
+    const GHOST_FILTER: bool = false;
+}
+
+// the same pcb with every diode mounted the other way round
+#[allow(unused)]
+pub struct WavierKeysReversed;
+
+impl Board<5, 15> for WavierKeysReversed {
+    const ROW_PINS: [u8; 5] = <WavierKeys as Board<5, 15>>::ROW_PINS;
+    const COL_PINS: [u8; 15] = <WavierKeys as Board<5, 15>>::COL_PINS;
+    const DIODES: DiodeDirection = DiodeDirection::Row2Col;
+    const SETTLE_NS: u32 = <WavierKeys as Board<5, 15>>::SETTLE_NS;
+    const GHOST_FILTER: bool = <WavierKeys as Board<5, 15>>::GHOST_FILTER;
+}
+
+// the board this firmware is built for, with its matrix size
+#[cfg(not(feature = "reversed-diodes"))]
+pub type ActiveBoard = WavierKeys;
+#[cfg(feature = "reversed-diodes")]
+pub type ActiveBoard = WavierKeysReversed;
+pub const ROWS: usize = 5;
+pub const COLS: usize = 15;
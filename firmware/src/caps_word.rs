@@ -0,0 +1,301 @@
+use crate::{
+    action::{Action, LSFT},
+    clock::{Duration, Instant},
+    keycodes::KeyCodes::{self, *},
+    overrides::SHIFT,
+    pipeline::{ActionEvent, ActionStage},
+};
+
+const CAPS_WORD_IDLE: Duration = Duration::secs(5); // caps word ends after this without a key
+
+#[derive(Clone, Copy)]
+enum WordKey {
+    // sent shifted
+    Shift,
+    // sent as it is
+    Keep,
+}
+
+// keycode ranges that keep a word going, every other key ends it. modifier
+// keys neither shift nor end the word.
+const WORD_KEYS: [(KeyCodes, KeyCodes, WordKey); 5] = [
+    (KeyA, KeyZ, WordKey::Shift),
+    (Key1, Key0, WordKey::Keep),
+    (Minus, Minus, WordKey::Shift), // - becomes _
+    (BackSpace, BackSpace, WordKey::Keep),
+    (Delete, Delete, WordKey::Keep),
+];
+
+// modifiers held by a modifier key, 0 for every other action
+fn held_mods(action: Action) -> u8 {
+    return match action {
+        Action::Key(code) => code.modifier_mask().unwrap_or(0),
+        Action::ModKey(mods, KeyCodes::Reserved) => mods,
+        _ => 0,
+    };
+}
+
+// shifts the letters of one word, then turns itself off. the host's caps lock
+// is left alone. a key
+// pressed with ctrl, alt or gui held is a shortcut and ends the word unshifted.
+// keys sent with shift of their own count as what caps word would make of
+// them: an auto shifted letter keeps the word going, a space cadet paren (a
+// shifted 9) ends it.
+pub struct CapsWord {
+    active: bool,
+    last_key: Instant,
+    // modifier keys down holding more than shift
+    other_mods_held: u8,
+}
+
+impl CapsWord {
+    pub fn new() -> Self {
+        CapsWord {
+            active: false,
+            last_key: Instant::from_ticks(0),
+            other_mods_held: 0,
+        }
+    }
+}
+
+impl ActionStage for CapsWord {
+    // the CapsWord action toggles it and goes no further
+    fn process(&mut self, event: ActionEvent, emit: &mut impl FnMut(ActionEvent)) {
+        if event.action == Action::CapsWord {
+            if event.key.pressed {
+                self.active = !self.active;
+                self.last_key = event.key.time;
+            }
+            return;
+        }
+        let mods = held_mods(event.action);
+        if mods & !SHIFT != 0 {
+            if event.key.pressed {
+                self.other_mods_held += 1;
+            } else {
+                self.other_mods_held = self.other_mods_held.saturating_sub(1);
+            }
+        }
+        if !self.active || !event.key.pressed || mods != 0 {
+            emit(event);
+            return;
+        }
+        let (code, shifted) = match event.action {
+            Action::Key(code) if self.other_mods_held == 0 => (code, false),
+            Action::ModKey(mods, code) if mods & !SHIFT == 0 && self.other_mods_held == 0 => {
+                (code, true)
+            }
+            // shortcuts
+            Action::Key(_) | Action::ModKey(..) => {
+                self.active = false;
+                emit(event);
+                return;
+            }
+            // mouse clicks don't type anything
+            _ => {
+                emit(event);
+                return;
+            }
+        };
+        self.last_key = event.key.time;
+        let action = match (code.find_range(&WORD_KEYS), shifted) {
+            (Some(WordKey::Shift), false) => LSFT(code),
+            (Some(WordKey::Shift), true) | (Some(WordKey::Keep), false) => event.action,
+            // shifted digits are symbols
+            (Some(WordKey::Keep), true) | (None, _) => {
+                self.active = false;
+                event.action
+            }
+        };
+        emit(ActionEvent { action, ..event });
+    }
+
+    fn tick(&mut self, now: Instant, _emit: &mut impl FnMut(ActionEvent)) {
+        if self.active && now - self.last_key > CAPS_WORD_IDLE {
+            self.active = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::{Clock, FakeClock},
+        layout::pos,
+        sim::{at, event},
+    };
+
+    // what caps word makes of `event`
+    fn send(caps_word: &mut CapsWord, event: ActionEvent) -> Option<Action> {
+        let mut sent = None;
+        caps_word.process(event, &mut |event| sent = Some(event.action));
+        return sent;
+    }
+
+    #[test]
+    fn word_ends_after_the_idle_time() {
+        let clock = FakeClock::new();
+        let mut caps_word = CapsWord::new();
+        assert_eq!(
+            send(
+                &mut caps_word,
+                event(pos(2, 0), true, Action::CapsWord, clock.now())
+            ),
+            None
+        );
+        clock.advance(CAPS_WORD_IDLE);
+        caps_word.tick(clock.now(), &mut |_| {});
+        let a = Action::Key(KeyA);
+        assert_eq!(
+            send(&mut caps_word, event(pos(2, 0), true, a, clock.now())),
+            Some(LSFT(KeyA))
+        );
+        // every key starts the wait again
+        clock.advance(CAPS_WORD_IDLE);
+        caps_word.tick(clock.now(), &mut |_| {});
+        assert_eq!(
+            send(&mut caps_word, event(pos(2, 0), true, a, clock.now())),
+            Some(LSFT(KeyA))
+        );
+        clock.advance(CAPS_WORD_IDLE + Duration::millis(1));
+        caps_word.tick(clock.now(), &mut |_| {});
+        assert_eq!(
+            send(&mut caps_word, event(pos(2, 0), true, a, clock.now())),
+            Some(a)
+        );
+    }
+
+    #[test]
+    fn shortcut_ends_the_word_unshifted() {
+        let mut caps_word = CapsWord::new();
+        let now = at(0);
+        send(
+            &mut caps_word,
+            event(pos(2, 0), true, Action::CapsWord, now),
+        );
+        let ctrl = Action::Key(LeftCtrl);
+        assert_eq!(
+            send(&mut caps_word, event(pos(2, 0), true, ctrl, now)),
+            Some(ctrl)
+        );
+        let c = Action::Key(KeyC);
+        assert_eq!(
+            send(&mut caps_word, event(pos(2, 0), true, c, now)),
+            Some(c)
+        );
+        send(&mut caps_word, event(pos(2, 0), false, ctrl, now));
+        let a = Action::Key(KeyA);
+        assert_eq!(
+            send(&mut caps_word, event(pos(2, 0), true, a, now)),
+            Some(a)
+        );
+    }
+
+    #[test]
+    fn shift_keeps_the_word_going() {
+        let mut caps_word = CapsWord::new();
+        let now = at(0);
+        send(
+            &mut caps_word,
+            event(pos(2, 0), true, Action::CapsWord, now),
+        );
+        let shift = Action::ModKey(0x02, Reserved);
+        assert_eq!(
+            send(&mut caps_word, event(pos(2, 0), true, shift, now)),
+            Some(shift)
+        );
+        send(&mut caps_word, event(pos(2, 0), false, shift, now));
+        let a = Action::Key(KeyA);
+        assert_eq!(
+            send(&mut caps_word, event(pos(2, 0), true, a, now)),
+            Some(LSFT(KeyA))
+        );
+    }
+
+    #[test]
+    fn auto_shifted_letter_keeps_the_word_going() {
+        let mut caps_word = CapsWord::new();
+        let now = at(0);
+        send(
+            &mut caps_word,
+            event(pos(2, 0), true, Action::CapsWord, now),
+        );
+        assert_eq!(
+            send(&mut caps_word, event(pos(2, 0), true, LSFT(KeyA), now)),
+            Some(LSFT(KeyA))
+        );
+        let b = Action::Key(KeyB);
+        assert_eq!(
+            send(&mut caps_word, event(pos(2, 0), true, b, now)),
+            Some(LSFT(KeyB))
+        );
+    }
+
+    #[test]
+    fn digits_are_kept_and_minus_becomes_underscore() {
+        let mut caps_word = CapsWord::new();
+        let now = at(0);
+        send(
+            &mut caps_word,
+            event(pos(2, 0), true, Action::CapsWord, now),
+        );
+        let two = Action::Key(Key2);
+        assert_eq!(
+            send(&mut caps_word, event(pos(2, 0), true, two, now)),
+            Some(two)
+        );
+        let minus = Action::Key(Minus);
+        assert_eq!(
+            send(&mut caps_word, event(pos(2, 0), true, minus, now)),
+            Some(LSFT(Minus))
+        );
+        let a = Action::Key(KeyA);
+        assert_eq!(
+            send(&mut caps_word, event(pos(2, 0), true, a, now)),
+            Some(LSFT(KeyA))
+        );
+    }
+
+    #[test]
+    fn space_and_punctuation_end_the_word() {
+        for end in [Space, Comma, Dot, Slash, Enter] {
+            let mut caps_word = CapsWord::new();
+            let now = at(0);
+            send(
+                &mut caps_word,
+                event(pos(2, 0), true, Action::CapsWord, now),
+            );
+            let end = Action::Key(end);
+            assert_eq!(
+                send(&mut caps_word, event(pos(2, 0), true, end, now)),
+                Some(end)
+            );
+            let a = Action::Key(KeyA);
+            assert_eq!(
+                send(&mut caps_word, event(pos(2, 0), true, a, now)),
+                Some(a)
+            );
+        }
+    }
+
+    #[test]
+    fn key_with_its_own_modifiers_ends_the_word() {
+        let mut caps_word = CapsWord::new();
+        let now = at(0);
+        send(
+            &mut caps_word,
+            event(pos(2, 0), true, Action::CapsWord, now),
+        );
+        // a space cadet paren
+        assert_eq!(
+            send(&mut caps_word, event(pos(2, 0), true, LSFT(Key9), now)),
+            Some(LSFT(Key9))
+        );
+        let a = Action::Key(KeyA);
+        assert_eq!(
+            send(&mut caps_word, event(pos(2, 0), true, a, now)),
+            Some(a)
+        );
+    }
+}
@@ -0,0 +1,43 @@
+use core::cell::Cell;
+
+#[cfg(target_os = "none")]
+use rp_pico::hal::Timer;
+
+// microseconds since boot, the rp2040 timer's own tick
+pub type Instant = fugit::TimerInstantU64<1_000_000>;
+pub type Duration = fugit::TimerDurationU64<1_000_000>;
+
+// monotonic time source for everything timed: idle, debounce, taps, the mouse
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+#[cfg(target_os = "none")]
+impl Clock for Timer {
+    fn now(&self) -> Instant {
+        return self.get_counter();
+    }
+}
+
+// clock that only moves when told to, used to run timed code off the board
+pub struct FakeClock {
+    now: Cell<Instant>,
+}
+
+impl FakeClock {
+    pub fn new() -> Self {
+        FakeClock {
+            now: Cell::new(Instant::from_ticks(0)),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.now.set(self.now.get() + by);
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+        return self.now.get();
+    }
+}
@@ -0,0 +1,125 @@
+use crate::{
+    clock::{Duration, Instant},
+    layout::KeyPos,
+    pipeline::{Debounce, KeyEvent},
+    MatrixState,
+};
+
+// a released key has to read open for this long before the release is accepted
+pub const DEBOUNCE: Duration = Duration::millis(5);
+
+// eager on presses, a key goes down on its first closed read so debouncing
+// adds no lag to typing. the contacts bouncing open right after are held off
+// by the release only going through once the key stayed open for DEBOUNCE.
+pub struct Debouncer<const ROWS: usize, const COLS: usize> {
+    stable: MatrixState<ROWS, COLS>,
+    // when each held key first read open
+    changed_at: [[Option<Instant>; COLS]; ROWS],
+}
+
+impl<const ROWS: usize, const COLS: usize> Debouncer<ROWS, COLS> {
+    pub fn new() -> Self {
+        Debouncer {
+            stable: [[false; COLS]; ROWS],
+            changed_at: [[None; COLS]; ROWS],
+        }
+    }
+}
+
+impl<const ROWS: usize, const COLS: usize> Debounce<ROWS, COLS> for Debouncer<ROWS, COLS> {
+    fn update(
+        &mut self,
+        raw: &MatrixState<ROWS, COLS>,
+        time: Instant,
+        emit: &mut impl FnMut(KeyEvent),
+    ) {
+        for row in 0..ROWS {
+            for col in 0..COLS {
+                if raw[row][col] == self.stable[row][col] {
+                    self.changed_at[row][col] = None;
+                    continue;
+                }
+                let since = *self.changed_at[row][col].get_or_insert(time);
+                if raw[row][col] || time - since >= DEBOUNCE {
+                    self.changed_at[row][col] = None;
+                    self.stable[row][col] = raw[row][col];
+                    emit(KeyEvent {
+                        pos: KeyPos { row, col },
+                        pressed: raw[row][col],
+                        time,
+                    });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::{Clock, FakeClock},
+        layout::pos,
+        sim::{key, keys},
+    };
+
+    // scans `raw` once a millisecond for `ms` milliseconds, returns what came out
+    fn scan(
+        debounce: &mut Debouncer<2, 3>,
+        clock: &FakeClock,
+        raw: &MatrixState<2, 3>,
+        ms: u64,
+    ) -> Vec<KeyEvent> {
+        let mut events = Vec::new();
+        for _ in 0..ms {
+            clock.advance(Duration::millis(1));
+            debounce.update(raw, clock.now(), &mut |event| events.push(event));
+        }
+        return events;
+    }
+
+    #[test]
+    fn press_goes_through_on_the_first_closed_read() {
+        let clock = FakeClock::new();
+        let mut debounce = Debouncer::new();
+        let down = keys(&[(1, 2)]);
+        let pressed_at = clock.now() + Duration::millis(1);
+        assert_eq!(
+            scan(&mut debounce, &clock, &down, 1),
+            [key(pos(1, 2), true, pressed_at)]
+        );
+        assert!(scan(&mut debounce, &clock, &down, 20).is_empty());
+    }
+
+    #[test]
+    fn release_goes_through_once_open_for_the_debounce_time() {
+        let clock = FakeClock::new();
+        let mut debounce = Debouncer::new();
+        let (down, up) = (keys(&[(1, 2)]), keys(&[]));
+        scan(&mut debounce, &clock, &down, 1);
+        assert!(scan(&mut debounce, &clock, &up, 5).is_empty());
+        let released_at = clock.now() + Duration::millis(1);
+        assert_eq!(
+            scan(&mut debounce, &clock, &up, 1),
+            [key(pos(1, 2), false, released_at)]
+        );
+        // pressed again right after, without waiting
+        let pressed_at = clock.now() + Duration::millis(1);
+        assert_eq!(
+            scan(&mut debounce, &clock, &down, 1),
+            [key(pos(1, 2), true, pressed_at)]
+        );
+    }
+
+    #[test]
+    fn bounces_of_a_held_key_are_dropped() {
+        let clock = FakeClock::new();
+        let mut debounce = Debouncer::new();
+        let (down, up) = (keys(&[(1, 2)]), keys(&[]));
+        assert_eq!(scan(&mut debounce, &clock, &down, 1).len(), 1);
+        for _ in 0..10 {
+            assert!(scan(&mut debounce, &clock, &up, 4).is_empty());
+            assert!(scan(&mut debounce, &clock, &down, 1).is_empty());
+        }
+    }
+}
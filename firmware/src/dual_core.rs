@@ -0,0 +1,86 @@
+use rp_pico::hal::{
+    multicore::Stack,
+    pac,
+    sio::{Sio, SioFifo},
+};
+
+use crate::{
+    board::{ActiveBoard, COLS, ROWS},
+    clock::{Clock, Duration, Instant},
+    debounce::Debouncer,
+    matrix::scan_key_switch,
+    pipeline::Debounce,
+    sio_matrix::SioMatrix,
+    MatrixState, SCAN_PERIOD_US,
+};
+
+pub static mut CORE1_STACK: Stack<4096> = Stack::new();
+
+// key events travel through the sio fifo as (pressed << 16) | (row << 8) | col
+fn encode_event(row: usize, col: usize, pressed: bool) -> u32 {
+    return (pressed as u32) << 16 | (row as u32) << 8 | col as u32;
+}
+
+fn decode_event(word: u32) -> (usize, usize, bool) {
+    return (
+        ((word >> 8) & 0xff) as usize,
+        (word & 0xff) as usize,
+        word >> 16 != 0,
+    );
+}
+
+// core1's clock. core0 owns the hal Timer, this only reads the free running
+// counter behind it, so both cores see the same time.
+struct Counter {
+    timer: pac::TIMER,
+}
+
+impl Clock for Counter {
+    fn now(&self) -> Instant {
+        // the raw registers don't latch, read again if the high word moved
+        loop {
+            let high = self.timer.timerawh.read().bits();
+            let low = self.timer.timerawl.read().bits();
+            if self.timer.timerawh.read().bits() == high {
+                return Instant::from_ticks((high as u64) << 32 | low as u64);
+            }
+        }
+    }
+}
+
+// core1 entry: scan and debounce the matrix, send every change to core0
+pub fn core1_task(sys_hz: u32) -> ! {
+    let pac = unsafe { pac::Peripherals::steal() };
+    let mut sio = Sio::new(pac.SIO);
+    let clock = Counter { timer: pac.TIMER };
+    let mut matrix_io = SioMatrix::<ActiveBoard, ROWS, COLS>::new(sys_hz);
+    let mut debouncer = Debouncer::<ROWS, COLS>::new();
+
+    let period = Duration::micros(SCAN_PERIOD_US as u64);
+    let mut next_scan = clock.now();
+    loop {
+        let now = clock.now();
+        if now < next_scan {
+            continue;
+        }
+        next_scan = now + period;
+        let (raw, _) = scan_key_switch::<ActiveBoard, ROWS, COLS>(&mut matrix_io);
+        debouncer.update(&raw, now, &mut |event| {
+            sio.fifo
+                .write_blocking(encode_event(event.pos.row, event.pos.col, event.pressed));
+        });
+    }
+}
+
+// core0 side: apply every pending event from core1 to the matrix state
+pub fn receive_events<const ROWS: usize, const COLS: usize>(
+    fifo: &mut SioFifo,
+    state: &mut MatrixState<ROWS, COLS>,
+) {
+    while let Some(word) = fifo.read() {
+        let (row, col, pressed) = decode_event(word);
+        if row < ROWS && col < COLS {
+            state[row][col] = pressed;
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use cortex_m::prelude::*;
+use embedded_hal::digital::v2::OutputPin;
+use fugit::ExtU32;
+
+#[cfg(feature = "dual-core")]
+use rp_pico::hal::multicore::Multicore;
+use rp_pico::{
+    self,
+    hal::{usb::UsbBus, Clock as _},
+};
+use usb_device::{
+    class_prelude::UsbBusAllocator,
+    prelude::{UsbDeviceBuilder, UsbVidPid},
+};
+use usbd_hid::{
+    descriptor::{KeyboardReport, MouseReport, SerializedDescriptor},
+    hid_class::{
+        HIDClass, HidClassSettings, HidCountryCode, HidProtocol, HidSubClass, ProtocolModeConfig,
+    },
+};
+
+#[cfg(not(feature = "dual-core"))]
+use crate::debounce::Debouncer;
+#[cfg(feature = "pio-scan")]
+use crate::pio_scan;
+use crate::{
+    board::{ActiveBoard, Board, COLS, ROWS},
+    clock::{Clock, Duration},
+    ghost::GhostFilter,
+    hooks::Hooks,
+    keyboard::Keyboard,
+    layout::{AUTO_SHIFT, KEY_LAYOUT, KEY_LAYOUT_WITH_FN, KEY_OVERRIDES},
+    report::{NO_BUTTONS, NO_KEYS},
+    SCAN_PERIOD_US,
+};
+#[cfg(feature = "dual-core")]
+use crate::{dual_core, pipeline::Edges, MatrixState};
+#[cfg(not(any(feature = "pio-scan", feature = "dual-core")))]
+use crate::{matrix::scan_key_switch, sio_matrix::SioMatrix};
+#[cfg(feature = "calibrate-settle")]
+use {crate::matrix::cycles_to_ns, cortex_m::peripheral::syst::SystClkSource};
+
+#[cfg(all(feature = "pio-scan", feature = "dual-core"))]
+compile_error!("features `pio-scan` and `dual-core` are mutually exclusive");
+#[cfg(all(feature = "pio-scan", feature = "reversed-diodes"))]
+compile_error!("feature `pio-scan` drives the rows, `reversed-diodes` needs the cpu scanner");
+#[cfg(all(
+    feature = "calibrate-settle",
+    any(feature = "pio-scan", feature = "dual-core")
+))]
+compile_error!("feature `calibrate-settle` needs the single core cpu scanner");
+
+#[derive(PartialEq)]
+enum KeyboardMode {
+    Normal,
+    Saving,
+}
+
+const SCAN_PERIOD_SAVING: Duration = Duration::millis(20); // matrix scan period while idle
+const KEYBOARD_POLL_MS: u8 = 1; // keyboard endpoint bInterval
+const MOUSE_POLL_MS: u8 = 1; // mouse endpoint bInterval
+const IDLE_WAIT: Duration = Duration::secs(5);
+const LED_BLINK: Duration = Duration::millis(250);
+
+const _: () = assert!(SCAN_PERIOD_SAVING.ticks() >= SCAN_PERIOD_US as u64);
+const _: () = assert!(KEYBOARD_POLL_MS >= 1 && MOUSE_POLL_MS >= 1);
+
+// the firmware's main loop: sets up the board and usb, then scans, runs the
+// key pipeline and reports forever. `hooks` plugs custom behaviour into it,
+// so a keymap crate depending on this library only needs its own entry point,
+// next to a defmt logger and panic handler like the ones in main.rs:
+//
+//   #[entry]
+//   fn main() -> ! {
+//       wavier_keys::firmware::run(MyHooks::default());
+//   }
+pub fn run<H: Hooks>(hooks: H) -> ! {
+    let mut dp = rp_pico::hal::pac::Peripherals::take().unwrap();
+    let mut watchdog = rp_pico::hal::Watchdog::new(dp.WATCHDOG);
+    let clocks = rp_pico::hal::clocks::init_clocks_and_plls(
+        rp_pico::XOSC_CRYSTAL_FREQ,
+        dp.XOSC,
+        dp.CLOCKS,
+        dp.PLL_SYS,
+        dp.PLL_USB,
+        &mut dp.RESETS,
+        &mut watchdog,
+    )
+    .ok()
+    .unwrap();
+    let timer = rp_pico::hal::Timer::new(dp.TIMER, &mut dp.RESETS);
+    let sys_hz = clocks.system_clock.freq().to_Hz();
+
+    let bus = UsbBus::new(
+        dp.USBCTRL_REGS,
+        dp.USBCTRL_DPRAM,
+        clocks.usb_clock,
+        true,
+        &mut dp.RESETS,
+    );
+    let bus_allocator = UsbBusAllocator::new(bus);
+
+    let mut kb_hid = HIDClass::new_with_settings(
+        &bus_allocator,
+        KeyboardReport::desc(),
+        KEYBOARD_POLL_MS,
+        HidClassSettings {
+            subclass: HidSubClass::NoSubClass,
+            protocol: HidProtocol::Keyboard,
+            config: ProtocolModeConfig::ForceReport,
+            locale: HidCountryCode::NotSupported,
+        },
+    );
+
+    let mut ms_hid = HIDClass::new_with_settings(
+        &bus_allocator,
+        MouseReport::desc(),
+        MOUSE_POLL_MS,
+        HidClassSettings {
+            subclass: HidSubClass::NoSubClass,
+            protocol: HidProtocol::Mouse,
+            config: ProtocolModeConfig::ForceReport,
+            locale: HidCountryCode::NotSupported,
+        },
+    );
+
+    let mut usb_dev = UsbDeviceBuilder::new(&bus_allocator, UsbVidPid(0x2718, 0x2818))
+        .manufacturer("Oya-Tomo")
+        .product("Wavier-Keys")
+        .serial_number("2023.9.13.18.57")
+        .build();
+
+    #[cfg_attr(not(feature = "dual-core"), allow(unused_mut))]
+    let mut sio = rp_pico::hal::Sio::new(dp.SIO);
+    let pins = rp_pico::Pins::new(dp.IO_BANK0, dp.PADS_BANK0, sio.gpio_bank0, &mut dp.RESETS);
+
+    let mut led = pins.led.into_push_pull_output();
+
+    #[cfg(not(any(feature = "pio-scan", feature = "dual-core")))]
+    let mut matrix_io = SioMatrix::<ActiveBoard, ROWS, COLS>::new(sys_hz);
+    #[cfg(feature = "calibrate-settle")]
+    let mut worst_recovery = 0;
+    #[cfg(feature = "calibrate-settle")]
+    {
+        let mut core = cortex_m::Peripherals::take().unwrap();
+        core.SYST.set_clock_source(SystClkSource::Core);
+        core.SYST.set_reload(0x00ff_ffff);
+        core.SYST.clear_current();
+        core.SYST.enable_counter();
+    }
+    #[cfg(feature = "pio-scan")]
+    let mut scanner =
+        pio_scan::PioScanner::<ActiveBoard, ROWS, COLS>::new(dp.PIO0, &mut dp.RESETS, sys_hz);
+    #[cfg(feature = "dual-core")]
+    {
+        let mut mc = Multicore::new(&mut dp.PSM, &mut dp.PPB, &mut sio.fifo);
+        let cores = mc.cores();
+        cores[1]
+            .spawn(
+                unsafe { &mut *core::ptr::addr_of_mut!(dual_core::CORE1_STACK.mem) },
+                move || dual_core::core1_task(sys_hz),
+            )
+            .unwrap();
+    }
+    #[cfg(feature = "dual-core")]
+    let mut matrix: MatrixState<ROWS, COLS> = [[false; COLS]; ROWS];
+
+    let mut countdown = timer.count_down();
+    countdown.start(SCAN_PERIOD_US.micros());
+
+    let mut keyboard_mode = KeyboardMode::Normal;
+    let mut last_input = timer.now();
+    let mut last_scan = timer.now();
+    let mut ghost_filter = GhostFilter::<ROWS, COLS>::new();
+    // core1 already debounces in dual core builds
+    #[cfg(not(feature = "dual-core"))]
+    let debounce = Debouncer::<ROWS, COLS>::new();
+    #[cfg(feature = "dual-core")]
+    let debounce = Edges::<ROWS, COLS>::new();
+    let mut keyboard = Keyboard::new(
+        debounce,
+        &KEY_LAYOUT,
+        &KEY_LAYOUT_WITH_FN,
+        &KEY_OVERRIDES,
+        AUTO_SHIFT,
+        hooks,
+    );
+
+    loop {
+        usb_dev.poll(&mut [&mut kb_hid, &mut ms_hid]);
+        #[cfg(feature = "pio-scan")]
+        scanner.poll();
+        #[cfg(feature = "dual-core")]
+        dual_core::receive_events(&mut sio.fifo, &mut matrix);
+        if countdown.wait().is_ok() {
+            let now = timer.now();
+            if keyboard_mode == KeyboardMode::Normal && now - last_input >= IDLE_WAIT {
+                keyboard_mode = KeyboardMode::Saving;
+                keyboard.idle(true);
+            }
+
+            if keyboard_mode == KeyboardMode::Normal || now - last_scan >= SCAN_PERIOD_SAVING {
+                last_scan = now;
+                #[cfg(not(any(feature = "pio-scan", feature = "dual-core")))]
+                let (mtx, empty) = scan_key_switch::<ActiveBoard, ROWS, COLS>(&mut matrix_io);
+                #[cfg(feature = "pio-scan")]
+                let (mtx, empty) = scanner.state();
+                #[cfg(feature = "dual-core")]
+                let (mtx, empty) = (matrix, !matrix.iter().flatten().any(|&key| key));
+                let mtx = if <ActiveBoard as Board<ROWS, COLS>>::GHOST_FILTER {
+                    ghost_filter.filter(&mtx)
+                } else {
+                    mtx
+                };
+                keyboard.scan(&mtx, now);
+                #[cfg(feature = "calibrate-settle")]
+                if let Some(cycles) = matrix_io.calibrate() {
+                    if cycles > worst_recovery {
+                        worst_recovery = cycles;
+                        defmt::info!(
+                            "settle calibration: recovered after {} cycles, safe SETTLE_NS {}",
+                            cycles,
+                            cycles_to_ns(cycles * 2, sys_hz)
+                        );
+                    }
+                }
+                let (keyboard_report, mouse_report) = keyboard.reports(now);
+                kb_hid.push_input(&keyboard_report).ok();
+                if ms_hid.push_input(&mouse_report).is_ok() {
+                    keyboard.mouse_sent(&mouse_report);
+                }
+                if !empty {
+                    last_input = now;
+                    if keyboard_mode == KeyboardMode::Saving {
+                        keyboard_mode = KeyboardMode::Normal;
+                        keyboard.idle(false);
+                    }
+                }
+            } else {
+                kb_hid.push_input(&NO_KEYS).ok();
+                ms_hid.push_input(&NO_BUTTONS).ok();
+            }
+        }
+        kb_hid.pull_raw_output(&mut [0; 64]).ok();
+        if keyboard.mouse_latched() {
+            // blink while mouse mode stays on without the key held
+            if timer.now().ticks() / LED_BLINK.ticks() % 2 == 0 {
+                led.set_high().unwrap();
+            } else {
+                led.set_low().unwrap();
+            }
+        } else if keyboard_mode == KeyboardMode::Normal {
+            led.set_high().unwrap();
+        } else {
+            led.set_low().unwrap();
+        }
+    }
+}
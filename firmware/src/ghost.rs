@@ -0,0 +1,118 @@
+use crate::MatrixState;
+
+// without working diodes, three closed switches on the corners of a rectangle
+// pull the fourth corner low too. a phantom key can't be told apart from a real
+// one, so every key on such a rectangle is held back unless it was already
+// accepted before the rectangle formed. held back keys are logged as warnings,
+// which the DEFMT_LOG in .cargo/config.toml keeps in the build.
+pub struct GhostFilter<const ROWS: usize, const COLS: usize> {
+    accepted: MatrixState<ROWS, COLS>,
+    held: MatrixState<ROWS, COLS>,
+}
+
+impl<const ROWS: usize, const COLS: usize> GhostFilter<ROWS, COLS> {
+    pub fn new() -> Self {
+        GhostFilter {
+            accepted: [[false; COLS]; ROWS],
+            held: [[false; COLS]; ROWS],
+        }
+    }
+
+    pub fn filter(&mut self, raw: &MatrixState<ROWS, COLS>) -> MatrixState<ROWS, COLS> {
+        let ambiguous = find_rectangles(raw);
+        for row in 0..ROWS {
+            for col in 0..COLS {
+                let held = ambiguous[row][col] && !self.accepted[row][col];
+                if held && !self.held[row][col] {
+                    defmt::warn!("ghost key held back at row {}, col {}", row, col);
+                }
+                self.held[row][col] = held;
+                self.accepted[row][col] = raw[row][col] && !held;
+            }
+        }
+        return self.accepted;
+    }
+}
+
+// marks every pressed key that shares two columns with another pressed row
+fn find_rectangles<const ROWS: usize, const COLS: usize>(
+    state: &MatrixState<ROWS, COLS>,
+) -> MatrixState<ROWS, COLS> {
+    let mut ambiguous: MatrixState<ROWS, COLS> = [[false; COLS]; ROWS];
+    for r1 in 0..ROWS {
+        for r2 in r1 + 1..ROWS {
+            let mut shared = 0;
+            for col in 0..COLS {
+                if state[r1][col] && state[r2][col] {
+                    shared += 1;
+                }
+            }
+            if shared < 2 {
+                continue;
+            }
+            for col in 0..COLS {
+                if state[r1][col] && state[r2][col] {
+                    ambiguous[r1][col] = true;
+                    ambiguous[r2][col] = true;
+                }
+            }
+        }
+    }
+    return ambiguous;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::keys;
+
+    #[test]
+    fn third_corner_of_a_rectangle_is_held_back() {
+        let mut filter = GhostFilter::<5, 15>::new();
+        filter.filter(&keys(&[(0, 0), (0, 1)]));
+        // (1, 0) goes down and drags the ghost (1, 1) along
+        let raw = keys(&[(0, 0), (0, 1), (1, 0), (1, 1)]);
+        assert_eq!(filter.filter(&raw), keys(&[(0, 0), (0, 1)]));
+        assert_eq!(filter.filter(&raw), keys(&[(0, 0), (0, 1)]));
+    }
+
+    #[test]
+    fn rectangle_appearing_at_once_is_held_back_entirely() {
+        let mut filter = GhostFilter::<5, 15>::new();
+        let raw = keys(&[(0, 0), (0, 1), (1, 0), (1, 1)]);
+        assert_eq!(filter.filter(&raw), keys(&[]));
+    }
+
+    #[test]
+    fn keys_accepted_before_the_rectangle_stay_down() {
+        let mut filter = GhostFilter::<5, 15>::new();
+        filter.filter(&keys(&[(1, 1)]));
+        filter.filter(&keys(&[(1, 1), (0, 1)]));
+        let three = keys(&[(1, 1), (0, 1), (0, 0)]);
+        assert_eq!(filter.filter(&three), three);
+        // the real fourth corner can't be told from a ghost
+        let raw = keys(&[(0, 0), (0, 1), (1, 0), (1, 1)]);
+        assert_eq!(filter.filter(&raw), three);
+    }
+
+    #[test]
+    fn held_back_key_comes_through_once_the_rectangle_breaks() {
+        let mut filter = GhostFilter::<5, 15>::new();
+        filter.filter(&keys(&[(0, 0), (0, 1)]));
+        filter.filter(&keys(&[(0, 0), (0, 1), (1, 0), (1, 1)]));
+        // (0, 1) is released, the ghost goes with it
+        assert_eq!(
+            filter.filter(&keys(&[(0, 0), (1, 0)])),
+            keys(&[(0, 0), (1, 0)])
+        );
+        assert_eq!(filter.filter(&keys(&[(0, 0)])), keys(&[(0, 0)]));
+        assert_eq!(filter.filter(&keys(&[])), keys(&[]));
+    }
+
+    #[test]
+    fn keys_in_one_row_or_column_are_not_ambiguous() {
+        let mut filter = GhostFilter::<5, 15>::new();
+        let raw = keys(&[(0, 0), (0, 1), (0, 2), (1, 2), (2, 2)]);
+        assert_eq!(filter.filter(&raw), raw);
+    }
+}
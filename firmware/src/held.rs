@@ -0,0 +1,193 @@
+use crate::{
+    action::Action,
+    clock::Instant,
+    keycodes::KeyCodes,
+    layout::{KeyPos, Keymap, FN_KEY_POS, MOUSE_ACTIONS, MOUSE_KEYS},
+    mouse_mode::MouseModeKey,
+    pipeline::{ActionEvent, KeyEvent, KeymapStage},
+};
+
+// where newly pressed keys resolve
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Layer {
+    Base,
+    Fn,
+    // the mouse keys, all other keys stay on Base or Fn
+    Mouse,
+}
+
+// keymap stage. a key is resolved once, when it goes down, and keeps that
+// action until it is released whatever fn and the mode key do in the
+// meantime. mouse mode only claims MOUSE_KEYS, every other key stays on the
+// keymaps.
+pub struct HeldKeys<const ROWS: usize, const COLS: usize> {
+    layout: &'static Keymap<ROWS, COLS>,
+    fn_layout: &'static Keymap<ROWS, COLS>,
+    actions: [[Option<(Action, Layer)>; COLS]; ROWS],
+    fn_held: bool,
+    mouse_mode: MouseModeKey,
+}
+
+impl<const ROWS: usize, const COLS: usize> HeldKeys<ROWS, COLS> {
+    pub fn new(
+        layout: &'static Keymap<ROWS, COLS>,
+        fn_layout: &'static Keymap<ROWS, COLS>,
+    ) -> Self {
+        HeldKeys {
+            layout,
+            fn_layout,
+            actions: [[None; COLS]; ROWS],
+            fn_held: false,
+            mouse_mode: MouseModeKey::new(),
+        }
+    }
+
+    // action of the key at `pos` if it went down now, and where it came from
+    fn resolve(&self, pos: KeyPos) -> (Action, Layer) {
+        if self.mouse_mode.active() {
+            if let Some(i) = MOUSE_KEYS.iter().position(|&key| key == pos) {
+                return (Action::Mouse(MOUSE_ACTIONS[i]), Layer::Mouse);
+            }
+        }
+        if self.fn_held {
+            return (self.fn_layout[pos.row][pos.col], Layer::Fn);
+        }
+        return (self.layout[pos.row][pos.col], Layer::Base);
+    }
+
+    pub fn layer(&self) -> Layer {
+        if self.mouse_mode.active() {
+            return Layer::Mouse;
+        }
+        if self.fn_held {
+            return Layer::Fn;
+        }
+        return Layer::Base;
+    }
+
+    pub fn mouse_latched(&self) -> bool {
+        return self.mouse_mode.latched();
+    }
+}
+
+impl<const ROWS: usize, const COLS: usize> KeymapStage for HeldKeys<ROWS, COLS> {
+    fn process(&mut self, event: KeyEvent) -> Option<ActionEvent> {
+        let KeyPos { row, col } = event.pos;
+        self.mouse_mode.process(&event);
+        if event.pos == FN_KEY_POS {
+            self.fn_held = event.pressed;
+        }
+        let (action, layer) = if event.pressed {
+            let resolved = self.resolve(event.pos);
+            if resolved.0 == Action::Key(KeyCodes::Reserved) {
+                return None;
+            }
+            self.actions[row][col] = Some(resolved);
+            resolved
+        } else {
+            self.actions[row][col].take()?
+        };
+        return Some(ActionEvent {
+            key: event,
+            action,
+            layer,
+        });
+    }
+
+    fn tick(&mut self, time: Instant) {
+        let busy = self.actions.iter().flatten().any(Option::is_some);
+        self.mouse_mode.tick(time, busy);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        action::MouseAction,
+        board::{COLS, ROWS},
+        layout::{KEY_LAYOUT, KEY_LAYOUT_WITH_FN, LEFT_BUTTON, MODE_KEY_POS, MOVE_LEFT},
+        pipeline::ActionProcessor,
+        report::Reports,
+        sim::{at, key},
+    };
+
+    fn keymap() -> HeldKeys<ROWS, COLS> {
+        return HeldKeys::new(&KEY_LAYOUT, &KEY_LAYOUT_WITH_FN);
+    }
+
+    fn resolved(event: Option<ActionEvent>) -> Option<(Action, Layer)> {
+        return event.map(|event| (event.action, event.layer));
+    }
+
+    #[test]
+    fn keys_resolve_on_the_base_layer() {
+        let mut keymap = keymap();
+        let a = KeyPos { row: 2, col: 1 };
+        let expected = Some((Action::Key(KeyCodes::KeyA), Layer::Base));
+        assert_eq!(resolved(keymap.process(key(a, true, at(0)))), expected);
+        assert_eq!(resolved(keymap.process(key(a, false, at(0)))), expected);
+    }
+
+    #[test]
+    fn fn_key_sends_nothing_and_switches_layer() {
+        let mut keymap = keymap();
+        assert_eq!(keymap.process(key(FN_KEY_POS, true, at(0))), None);
+        assert_eq!(keymap.layer(), Layer::Fn);
+        let one = KeyPos { row: 0, col: 1 };
+        let f1 = Some((Action::Key(KeyCodes::F1), Layer::Fn));
+        assert_eq!(resolved(keymap.process(key(one, true, at(0)))), f1);
+        assert_eq!(keymap.process(key(FN_KEY_POS, false, at(0))), None);
+        assert_eq!(keymap.layer(), Layer::Base);
+        // released on the layer it was pressed on
+        assert_eq!(resolved(keymap.process(key(one, false, at(0)))), f1);
+    }
+
+    #[test]
+    fn mode_key_claims_the_mouse_keys_only() {
+        let mut keymap = keymap();
+        assert_eq!(keymap.process(key(MODE_KEY_POS, true, at(0))), None);
+        assert_eq!(keymap.layer(), Layer::Mouse);
+        let left = Some((Action::Mouse(MouseAction::MoveLeft), Layer::Mouse));
+        assert_eq!(resolved(keymap.process(key(MOVE_LEFT, true, at(0)))), left);
+        let q = KeyPos { row: 1, col: 1 };
+        let expected = Some((Action::Key(KeyCodes::KeyQ), Layer::Base));
+        assert_eq!(resolved(keymap.process(key(q, true, at(0)))), expected);
+    }
+
+    // feeds one key change through the keymap into the reports
+    fn send(keymap: &mut HeldKeys<ROWS, COLS>, reports: &mut Reports, pos: KeyPos, pressed: bool) {
+        if let Some(event) = keymap.process(key(pos, pressed, at(0))) {
+            reports.process(event);
+        }
+    }
+
+    #[test]
+    fn key_held_into_mouse_mode_is_released_on_the_keyboard() {
+        let mut keymap = keymap();
+        let mut reports = Reports::new(&[]);
+        send(&mut keymap, &mut reports, MOVE_LEFT, true);
+        let h = KeyCodes::KeyH as u8;
+        assert_eq!(reports.keyboard_report().keycodes, [h, 0, 0, 0, 0, 0]);
+        send(&mut keymap, &mut reports, MODE_KEY_POS, true);
+        assert_eq!(reports.keyboard_report().keycodes, [h, 0, 0, 0, 0, 0]);
+        send(&mut keymap, &mut reports, MOVE_LEFT, false);
+        assert_eq!(reports.keyboard_report().keycodes, [0; 6]);
+        let report = reports.mouse_report(at(0));
+        assert_eq!((report.buttons, report.x, report.y), (0, 0, 0));
+    }
+
+    #[test]
+    fn button_held_out_of_mouse_mode_is_released_on_the_mouse() {
+        let mut keymap = keymap();
+        let mut reports = Reports::new(&[]);
+        send(&mut keymap, &mut reports, MODE_KEY_POS, true);
+        send(&mut keymap, &mut reports, LEFT_BUTTON, true);
+        assert_eq!(reports.mouse_report(at(0)).buttons, 1);
+        send(&mut keymap, &mut reports, MODE_KEY_POS, false);
+        assert_eq!(reports.mouse_report(at(0)).buttons, 1);
+        send(&mut keymap, &mut reports, LEFT_BUTTON, false);
+        assert_eq!(reports.mouse_report(at(0)).buttons, 0);
+        assert_eq!(reports.keyboard_report().keycodes, [0; 6]);
+    }
+}
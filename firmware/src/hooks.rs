@@ -0,0 +1,30 @@
+use usbd_hid::descriptor::{KeyboardReport, MouseReport};
+
+use crate::{held::Layer, pipeline::KeyEvent};
+
+// extension points for custom behaviour that would otherwise mean editing the
+// report code. a keymap crate depends on this library, implements Hooks for
+// its own type and passes it to firmware::run from its entry point, the way
+// main.rs passes NoHooks. every hook has a default that leaves the firmware
+// as it is.
+pub trait Hooks {
+    // sees every key event before the keymap does. return false to swallow it.
+    // events passed to `inject` reach the keymap right away, ahead of `event`.
+    fn process_event(&mut self, _event: &KeyEvent, _inject: &mut impl FnMut(KeyEvent)) -> bool {
+        return true;
+    }
+
+    // the layer newly pressed keys resolve on changed
+    fn on_layer_change(&mut self, _layer: Layer) {}
+
+    // the keyboard went idle (true) or woke up again (false)
+    fn on_idle(&mut self, _idle: bool) {}
+
+    // last look at both reports before they are handed to usb
+    fn post_report(&mut self, _keyboard: &mut KeyboardReport, _mouse: &mut MouseReport) {}
+}
+
+#[derive(Default)]
+pub struct NoHooks;
+
+impl Hooks for NoHooks {}
@@ -0,0 +1,252 @@
+use usbd_hid::descriptor::{KeyboardReport, MouseReport};
+
+use crate::{
+    auto_shift::{AutoShift, AutoShiftTimeouts},
+    caps_word::CapsWord,
+    clock::Instant,
+    held::{HeldKeys, Layer},
+    hooks::Hooks,
+    layout::Keymap,
+    overrides::KeyOverride,
+    pipeline::{ActionProcessor, ActionStage, Combo, Debounce, KeymapStage, NoCombos},
+    report::Reports,
+    space_cadet::SpaceCadet,
+    MatrixState,
+};
+
+// every stage of the key pipeline wired together, from a scanned matrix to
+// the two reports. the firmware loop feeds it one matrix per scan and sends
+// what comes out, the board itself stays outside so it runs off the board too.
+pub struct Keyboard<D, H, const ROWS: usize, const COLS: usize> {
+    debounce: D,
+    combos: NoCombos,
+    keymap: HeldKeys<ROWS, COLS>,
+    space_cadet: SpaceCadet,
+    auto_shift: AutoShift,
+    caps_word: CapsWord,
+    reports: Reports,
+    hooks: H,
+    // last layer on_layer_change was called with
+    layer: Layer,
+}
+
+impl<D: Debounce<ROWS, COLS>, H: Hooks, const ROWS: usize, const COLS: usize>
+    Keyboard<D, H, ROWS, COLS>
+{
+    pub fn new(
+        debounce: D,
+        layout: &'static Keymap<ROWS, COLS>,
+        fn_layout: &'static Keymap<ROWS, COLS>,
+        overrides: &'static [KeyOverride],
+        auto_shift: AutoShiftTimeouts,
+        hooks: H,
+    ) -> Self {
+        let keymap = HeldKeys::new(layout, fn_layout);
+        let layer = keymap.layer();
+        Keyboard {
+            debounce,
+            combos: NoCombos,
+            keymap,
+            space_cadet: SpaceCadet::new(),
+            auto_shift: AutoShift::new(auto_shift),
+            caps_word: CapsWord::new(),
+            reports: Reports::new(overrides),
+            hooks,
+            layer,
+        }
+    }
+
+    // runs one scan through the stages in order: debounce, combos, hooks,
+    // keymap, space cadet, auto shift, caps word and the report builder
+    pub fn scan(&mut self, matrix: &MatrixState<ROWS, COLS>, now: Instant) {
+        let Keyboard {
+            debounce,
+            combos,
+            keymap,
+            space_cadet,
+            auto_shift,
+            caps_word,
+            reports,
+            hooks,
+            layer,
+        } = self;
+        let mut finish = |event| caps_word.process(event, &mut |event| reports.process(event));
+        let mut shift = |event| auto_shift.process(event, &mut finish);
+        let mut deliver = |event| {
+            if let Some(event) = keymap.process(event) {
+                space_cadet.process(event, &mut shift);
+            }
+        };
+        let mut resolve = |event| {
+            if hooks.process_event(&event, &mut deliver) {
+                deliver(event);
+            }
+        };
+        debounce.update(matrix, now, &mut |event| {
+            combos.process(event, &mut resolve)
+        });
+        combos.tick(now, &mut resolve);
+        keymap.tick(now);
+        space_cadet.tick(now, &mut shift);
+        auto_shift.tick(now, &mut finish);
+        caps_word.tick(now, &mut |event| reports.process(event));
+        if keymap.layer() != *layer {
+            *layer = keymap.layer();
+            hooks.on_layer_change(*layer);
+        }
+    }
+
+    // both reports of this scan, after the hooks had their last look.
+    // mouse mode is a layer over the mouse keys, both interfaces report every
+    // scan so modifiers apply to clicks.
+    pub fn reports(&mut self, now: Instant) -> (KeyboardReport, MouseReport) {
+        let mut keyboard = self.reports.keyboard_report();
+        let mut mouse = self.reports.mouse_report(now);
+        self.hooks.post_report(&mut keyboard, &mut mouse);
+        return (keyboard, mouse);
+    }
+
+    // the host took `report`
+    pub fn mouse_sent(&mut self, report: &MouseReport) {
+        self.reports.mouse_sent(report);
+    }
+
+    pub fn idle(&mut self, idle: bool) {
+        self.hooks.on_idle(idle);
+    }
+
+    pub fn mouse_latched(&self) -> bool {
+        return self.keymap.mouse_latched();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        board::{COLS, ROWS},
+        clock::{Clock, Duration, FakeClock},
+        hooks::NoHooks,
+        keycodes::KeyCodes::*,
+        layout::{KeyPos, FN_KEY_POS, KEY_LAYOUT, KEY_LAYOUT_WITH_FN, KEY_OVERRIDES},
+        pipeline::{Edges, KeyEvent},
+        sim::keys,
+    };
+
+    const NO_AUTO_SHIFT: AutoShiftTimeouts = AutoShiftTimeouts {
+        letters: None,
+        numbers: None,
+        symbols: None,
+    };
+
+    fn keyboard<H: Hooks>(hooks: H) -> Keyboard<Edges<ROWS, COLS>, H, ROWS, COLS> {
+        return Keyboard::new(
+            Edges::new(),
+            &KEY_LAYOUT,
+            &KEY_LAYOUT_WITH_FN,
+            &KEY_OVERRIDES,
+            NO_AUTO_SHIFT,
+            hooks,
+        );
+    }
+
+    // scans `pressed` a millisecond after the last scan, returns the keyboard report
+    fn scan<H: Hooks>(
+        keyboard: &mut Keyboard<Edges<ROWS, COLS>, H, ROWS, COLS>,
+        clock: &FakeClock,
+        pressed: &[(usize, usize)],
+    ) -> KeyboardReport {
+        clock.advance(Duration::millis(1));
+        keyboard.scan(&keys(pressed), clock.now());
+        return keyboard.reports(clock.now()).0;
+    }
+
+    const A: (usize, usize) = (2, 1);
+    const B: (usize, usize) = (3, 5);
+
+    // swallows b
+    struct NoB;
+
+    impl Hooks for NoB {
+        fn process_event(&mut self, event: &KeyEvent, _inject: &mut impl FnMut(KeyEvent)) -> bool {
+            return event.pos != KeyPos { row: B.0, col: B.1 };
+        }
+    }
+
+    // types b along with a
+    struct AlsoB;
+
+    impl Hooks for AlsoB {
+        fn process_event(&mut self, event: &KeyEvent, inject: &mut impl FnMut(KeyEvent)) -> bool {
+            if event.pos == (KeyPos { row: A.0, col: A.1 }) {
+                inject(KeyEvent {
+                    pos: KeyPos { row: B.0, col: B.1 },
+                    ..*event
+                });
+            }
+            return true;
+        }
+    }
+
+    #[derive(Default)]
+    struct Layers(Vec<Layer>);
+
+    impl Hooks for Layers {
+        fn on_layer_change(&mut self, layer: Layer) {
+            self.0.push(layer);
+        }
+    }
+
+    #[test]
+    fn hook_swallows_an_event() {
+        let clock = FakeClock::new();
+        let mut keyboard = keyboard(NoB);
+        let report = scan(&mut keyboard, &clock, &[A, B]);
+        assert_eq!(report.keycodes, [KeyA as u8, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn hook_injects_an_event_ahead_of_the_original() {
+        let clock = FakeClock::new();
+        let mut keyboard = keyboard(AlsoB);
+        let report = scan(&mut keyboard, &clock, &[A]);
+        assert_eq!(report.keycodes, [KeyB as u8, KeyA as u8, 0, 0, 0, 0]);
+        let report = scan(&mut keyboard, &clock, &[]);
+        assert_eq!(report.keycodes, [0; 6]);
+    }
+
+    #[test]
+    fn layer_changes_reach_the_hooks() {
+        let clock = FakeClock::new();
+        let mut keyboard = keyboard(Layers::default());
+        let fn_key = (FN_KEY_POS.row, FN_KEY_POS.col);
+        scan(&mut keyboard, &clock, &[fn_key]);
+        scan(&mut keyboard, &clock, &[fn_key, A]);
+        scan(&mut keyboard, &clock, &[]);
+        assert_eq!(keyboard.hooks.0, [Layer::Fn, Layer::Base]);
+    }
+
+    #[test]
+    fn keys_pass_the_action_stages_in_order() {
+        let clock = FakeClock::new();
+        let mut keyboard = keyboard(NoHooks);
+        // caps word on the fn layer, then a space cadet tap
+        let fn_key = (FN_KEY_POS.row, FN_KEY_POS.col);
+        scan(&mut keyboard, &clock, &[fn_key]);
+        scan(&mut keyboard, &clock, &[fn_key, (2, 0)]);
+        scan(&mut keyboard, &clock, &[]);
+        let report = scan(&mut keyboard, &clock, &[A]);
+        assert_eq!(report.modifier, 0x02);
+        assert_eq!(report.keycodes, [KeyA as u8, 0, 0, 0, 0, 0]);
+        scan(&mut keyboard, &clock, &[]);
+        scan(&mut keyboard, &clock, &[(3, 0)]);
+        // the paren goes out shifted on release and ends the word
+        let report = scan(&mut keyboard, &clock, &[]);
+        assert_eq!(report.modifier, 0x02);
+        assert_eq!(report.keycodes, [Key9 as u8, 0, 0, 0, 0, 0]);
+        scan(&mut keyboard, &clock, &[]);
+        let report = scan(&mut keyboard, &clock, &[A]);
+        assert_eq!(report.modifier, 0);
+        assert_eq!(report.keycodes, [KeyA as u8, 0, 0, 0, 0, 0]);
+    }
+}
@@ -265,6 +265,27 @@ pub enum KeyCodes {
     MediaCalc = 0xfb,
 }
 
+impl KeyCodes {
+    // report modifier bit of LeftCtrl..RightMeta, None for every other key
+    pub fn modifier_mask(self) -> Option<u8> {
+        let code = self as u8;
+        if code >= KeyCodes::LeftCtrl as u8 && code <= KeyCodes::RightMeta as u8 {
+            return Some(1 << (code - KeyCodes::LeftCtrl as u8));
+        }
+        return None;
+    }
+
+    // value of the first (first, last, value) entry whose keycode range holds
+    // this key, for tables sorting keys into classes
+    pub fn find_range<T: Copy>(self, ranges: &[(KeyCodes, KeyCodes, T)]) -> Option<T> {
+        let code = self as u8;
+        return ranges
+            .iter()
+            .find(|(first, last, _)| *first as u8 <= code && code <= *last as u8)
+            .map(|&(_, _, value)| value);
+    }
+}
+
 #[allow(unused)]
 pub enum ModifierMasks {
     LeftCtrl = 1 << 0,
@@ -1,86 +1,231 @@
 use super::keycodes::KeyCodes::*;
+use crate::action::{k, Action, MouseAction, LSFT, SC_LSPO, SC_RSPC};
+use crate::auto_shift::AutoShiftTimeouts;
+use crate::board::{COLS, ROWS};
+use crate::held::Layer;
 use crate::keycodes::KeyCodes;
+use crate::overrides::{KeyOverride, SHIFT};
 
-pub const MODE_KEY_POS: [usize; 2] = [3, 14]; // switch keyboard mode or mouse mode
+pub type Keymap<const ROWS: usize, const COLS: usize> = [[Action; COLS]; ROWS];
 
-pub const FN_KEY_POS: [usize; 2] = [2, 14]; // row, col
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyPos {
+    pub row: usize,
+    pub col: usize,
+}
 
-pub const LEFT_CTRL: [usize; 2] = [4, 0];
-pub const LEFT_SHIFT: [usize; 2] = [3, 0];
-pub const LEFT_ALT: [usize; 2] = [4, 2];
-pub const LEFT_GUI: [usize; 2] = [4, 1];
-pub const RIGHT_CTRL: [usize; 2] = [4, 10];
-pub const RIGHT_SHIFT: [usize; 2] = [3, 11];
-pub const RIGHT_ALT: [usize; 2] = [4, 8];
-pub const RIGHT_GUI: [usize; 2] = [4, 9]; // unused in the layout
+pub const fn pos(row: usize, col: usize) -> KeyPos {
+    KeyPos { row, col }
+}
 
-pub const MOVE_LEFT: [usize; 2] = [2, 6];
-pub const MOVE_DOWN: [usize; 2] = [2, 7];
-pub const MOVE_UP: [usize; 2] = [2, 8];
-pub const MOVE_RIGHT: [usize; 2] = [2, 9];
-pub const LEFT_BUTTON: [usize; 2] = [2, 10];
-pub const RIGHT_BUTTON: [usize; 2] = [2, 11];
+pub const MODE_KEY_POS: KeyPos = pos(3, 14); // switch keyboard mode or mouse mode
 
-pub const KEY_LAYOUT: [[KeyCodes; 15]; 5] = [
+pub const FN_KEY_POS: KeyPos = pos(2, 14);
+
+pub const MOVE_LEFT: KeyPos = pos(2, 6);
+pub const MOVE_DOWN: KeyPos = pos(2, 7);
+pub const MOVE_UP: KeyPos = pos(2, 8);
+pub const MOVE_RIGHT: KeyPos = pos(2, 9);
+pub const LEFT_BUTTON: KeyPos = pos(2, 10);
+pub const RIGHT_BUTTON: KeyPos = pos(2, 11);
+
+// keys acting on the matrix state directly, they must not send a keycode too
+const STATE_KEYS: [KeyPos; 2] = [MODE_KEY_POS, FN_KEY_POS];
+
+// keys that drive the mouse while the mode key is held, the rest keep typing
+pub const MOUSE_KEYS: [KeyPos; 6] = [
+    MOVE_LEFT,
+    MOVE_DOWN,
+    MOVE_UP,
+    MOVE_RIGHT,
+    LEFT_BUTTON,
+    RIGHT_BUTTON,
+];
+
+// what each of MOUSE_KEYS does in mouse mode
+pub const MOUSE_ACTIONS: [MouseAction; 6] = [
+    MouseAction::MoveLeft,
+    MouseAction::MoveDown,
+    MouseAction::MoveUp,
+    MouseAction::MoveRight,
+    MouseAction::LeftButton,
+    MouseAction::RightButton,
+];
+
+const fn in_matrix(keys: &[KeyPos]) -> bool {
+    let mut i = 0;
+    while i < keys.len() {
+        if keys[i].row >= ROWS || keys[i].col >= COLS {
+            return false;
+        }
+        i += 1;
+    }
+    return true;
+}
+
+const fn same(a: KeyPos, b: KeyPos) -> bool {
+    a.row == b.row && a.col == b.col
+}
+
+const fn distinct(keys: &[KeyPos]) -> bool {
+    let mut i = 0;
+    while i < keys.len() {
+        let mut j = i + 1;
+        while j < keys.len() {
+            if same(keys[i], keys[j]) {
+                return false;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    return true;
+}
+
+const fn disjoint(a: &[KeyPos], b: &[KeyPos]) -> bool {
+    let mut i = 0;
+    while i < a.len() {
+        let mut j = 0;
+        while j < b.len() {
+            if same(a[i], b[j]) {
+                return false;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    return true;
+}
+
+const fn reserved(layout: &Keymap<ROWS, COLS>, keys: &[KeyPos]) -> bool {
+    let mut i = 0;
+    while i < keys.len() {
+        if !matches!(
+            layout[keys[i].row][keys[i].col],
+            Action::Key(KeyCodes::Reserved)
+        ) {
+            return false;
+        }
+        i += 1;
+    }
+    return true;
+}
+
+const _: () = assert!(in_matrix(&STATE_KEYS), "special key outside the matrix");
+const _: () = assert!(in_matrix(&MOUSE_KEYS), "mouse key outside the matrix");
+const _: () = assert!(distinct(&STATE_KEYS), "two special keys share a position");
+const _: () = assert!(distinct(&MOUSE_KEYS), "two mouse keys share a position");
+const _: () = assert!(
+    disjoint(&STATE_KEYS, &MOUSE_KEYS),
+    "mouse key on a special key"
+);
+const _: () = assert!(
+    reserved(&KEY_LAYOUT, &STATE_KEYS),
+    "special key also sends a keycode"
+);
+const _: () = assert!(
+    reserved(&KEY_LAYOUT_WITH_FN, &STATE_KEYS),
+    "special key also sends a keycode on the fn layer"
+);
+
+// shift+backspace deletes forward, shift+escape types ~ on the base layer
+pub const KEY_OVERRIDES: [KeyOverride; 2] = [
+    KeyOverride::new(SHIFT, BackSpace, k(Delete)),
+    KeyOverride::new(SHIFT, Escape, LSFT(Grave)).on(Layer::Base),
+];
+
+// hold a key this long to send it shifted, e.g. Some(Duration::millis(175)).
+// None keeps the class as it is.
+pub const AUTO_SHIFT: AutoShiftTimeouts = AutoShiftTimeouts {
+    letters: None,
+    numbers: None,
+    symbols: None,
+};
+
+#[rustfmt::skip]
+pub const KEY_LAYOUT: Keymap<ROWS, COLS> = [
     [
-        Escape, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0, Minus, Equal, Grave,
-        BackSpace,
+        k(Escape),    k(Key1),       k(Key2),       k(Key3),      k(Key4),
+        k(Key5),      k(Key6),       k(Key7),       k(Key8),      k(Key9),
+        k(Key0),      k(Minus),      k(Equal),      k(Grave),     k(BackSpace),
     ],
     [
-        Tab, KeyQ, KeyW, KeyE, KeyR, KeyT, KeyY, KeyU, KeyI, KeyO, KeyP, LeftBrace, RightBrace,
-        BackSlash, Delete,
+        k(Tab),       k(KeyQ),       k(KeyW),       k(KeyE),      k(KeyR),
+        k(KeyT),      k(KeyY),       k(KeyU),       k(KeyI),      k(KeyO),
+        k(KeyP),      k(LeftBrace),  k(RightBrace), k(BackSlash), k(Delete),
     ],
     [
-        CapsLock, KeyA, KeyS, KeyD, KeyF, KeyG, KeyH, KeyJ, KeyK, KeyL, SemiColon, Apostrophe,
-        Reserved, Enter, Reserved,
+        k(CapsLock),  k(KeyA),       k(KeyS),       k(KeyD),      k(KeyF),
+        k(KeyG),      k(KeyH),       k(KeyJ),       k(KeyK),      k(KeyL),
+        k(SemiColon), k(Apostrophe), k(Reserved),   k(Enter),     k(Reserved),
     ],
     [
-        Reserved, KeyZ, KeyX, KeyC, KeyV, KeyB, KeyN, KeyM, Comma, Dot, Slash, Reserved, Reserved,
-        Up, Reserved,
+        SC_LSPO,      k(KeyZ),       k(KeyX),       k(KeyC),      k(KeyV),
+        k(KeyB),      k(KeyN),       k(KeyM),       k(Comma),     k(Dot),
+        k(Slash),     SC_RSPC,       k(Reserved),   k(Up),        k(Reserved),
     ],
     [
-        Reserved, Reserved, Reserved, Reserved, Reserved, Space, Reserved, Reserved, Reserved,
-        Reserved, Reserved, Left, Reserved, Down, Right,
+        k(LeftCtrl),  k(LeftMeta),   k(LeftAlt),    k(Reserved),  k(Reserved),
+        k(Space),     k(Reserved),   k(Reserved),   k(RightAlt),  k(RightMeta),
+        k(RightCtrl), k(Left),       k(Reserved),   k(Down),      k(Right),
     ],
 ];
 
-pub const KEY_LAYOUT_WITH_FN: [[KeyCodes; 15]; 5] = [
+#[rustfmt::skip]
+pub const KEY_LAYOUT_WITH_FN: Keymap<ROWS, COLS> = [
     [
-        Escape, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12, F13, BackSpace,
+        k(Escape),        k(F1),         k(F2),         k(F3),        k(F4),
+        k(F5),            k(F6),         k(F7),         k(F8),        k(F9),
+        k(F10),           k(F11),        k(F12),        k(F13),       k(BackSpace),
     ],
     [
-        Tab, KeyQ, KeyW, KeyE, KeyR, KeyT, KeyY, KeyU, KeyI, KeyO, KeyP, LeftBrace, RightBrace,
-        BackSlash, Delete,
+        k(Tab),           k(KeyQ),       k(KeyW),       k(KeyE),      k(KeyR),
+        k(KeyT),          k(KeyY),       k(KeyU),       k(KeyI),      k(KeyO),
+        k(KeyP),          k(LeftBrace),  k(RightBrace), k(BackSlash), k(Delete),
     ],
     [
-        CapsLock, KeyA, KeyS, KeyD, KeyF, KeyG, KeyH, KeyJ, KeyK, KeyL, SemiColon, Apostrophe,
-        Reserved, Enter, Reserved,
+        Action::CapsWord, k(KeyA),       k(KeyS),       k(KeyD),      k(KeyF),
+        k(KeyG),          k(KeyH),       k(KeyJ),       k(KeyK),      k(KeyL),
+        k(SemiColon),     k(Apostrophe), k(Reserved),   k(Enter),     k(Reserved),
     ],
     [
-        Reserved, KeyZ, KeyX, KeyC, KeyV, KeyB, KeyN, KeyM, Comma, Dot, Slash, Reserved, Reserved,
-        Up, Reserved,
+        SC_LSPO,          k(KeyZ),       k(KeyX),       k(KeyC),      k(KeyV),
+        k(KeyB),          k(KeyN),       k(KeyM),       k(Comma),     k(Dot),
+        k(Slash),         SC_RSPC,       k(Reserved),   k(Up),        k(Reserved),
     ],
     [
-        Reserved, Reserved, Reserved, Reserved, Reserved, Space, Reserved, Reserved, Reserved,
-        Reserved, Reserved, Left, Reserved, Down, Right,
+        k(LeftCtrl),      k(LeftMeta),   k(LeftAlt),    k(Reserved),  k(Reserved),
+        k(Space),         k(Reserved),   k(Reserved),   k(RightAlt),  k(RightMeta),
+        k(RightCtrl),     k(Left),       k(Reserved),   k(Down),      k(Right),
     ],
 ];
 
 #[allow(unused)]
-pub const TEST_KEY_LAYOUT: [[KeyCodes; 15]; 5] = [
+#[rustfmt::skip]
+pub const TEST_KEY_LAYOUT: Keymap<ROWS, COLS> = [
     [
-        KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA,
+        k(KeyA), k(KeyA), k(KeyA), k(KeyA), k(KeyA),
+        k(KeyA), k(KeyA), k(KeyA), k(KeyA), k(KeyA),
+        k(KeyA), k(KeyA), k(KeyA), k(KeyA), k(KeyA),
     ],
     [
-        KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA,
+        k(KeyA), k(KeyA), k(KeyA), k(KeyA), k(KeyA),
+        k(KeyA), k(KeyA), k(KeyA), k(KeyA), k(KeyA),
+        k(KeyA), k(KeyA), k(KeyA), k(KeyA), k(KeyA),
     ],
     [
-        KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA,
+        k(KeyA), k(KeyA), k(KeyA), k(KeyA), k(KeyA),
+        k(KeyA), k(KeyA), k(KeyA), k(KeyA), k(KeyA),
+        k(KeyA), k(KeyA), k(KeyA), k(KeyA), k(KeyA),
     ],
     [
-        KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA,
+        k(KeyA), k(KeyA), k(KeyA), k(KeyA), k(KeyA),
+        k(KeyA), k(KeyA), k(KeyA), k(KeyA), k(KeyA),
+        k(KeyA), k(KeyA), k(KeyA), k(KeyA), k(KeyA),
     ],
     [
-        KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA, KeyA,
+        k(KeyA), k(KeyA), k(KeyA), k(KeyA), k(KeyA),
+        k(KeyA), k(KeyA), k(KeyA), k(KeyA), k(KeyA),
+        k(KeyA), k(KeyA), k(KeyA), k(KeyA), k(KeyA),
     ],
 ];
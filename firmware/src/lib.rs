@@ -0,0 +1,45 @@
+#![cfg_attr(not(test), no_std)]
+
+// everything the firmware is made of apart from its entry point in main.rs,
+// which only hands firmware::run the hooks it is built with.
+// the modules that don't touch the rp2040 build on any target, their tests
+// run on the development machine:
+//
+//   cargo test --lib --target <host triple>
+
+pub mod action;
+pub mod auto_shift;
+pub mod board;
+pub mod caps_word;
+pub mod clock;
+pub mod debounce;
+pub mod ghost;
+pub mod held;
+pub mod hooks;
+pub mod keyboard;
+pub mod keycodes;
+pub mod layout;
+pub mod matrix;
+pub mod mouse_mode;
+pub mod overrides;
+pub mod pipeline;
+pub mod report;
+#[cfg(test)]
+mod sim;
+pub mod slots;
+pub mod space_cadet;
+
+// rp2040 only
+#[cfg(all(target_os = "none", feature = "dual-core"))]
+pub mod dual_core;
+#[cfg(target_os = "none")]
+pub mod firmware;
+#[cfg(all(target_os = "none", feature = "pio-scan"))]
+pub mod pio_scan;
+#[cfg(target_os = "none")]
+pub mod sio_matrix;
+
+pub type MatrixState<const ROWS: usize, const COLS: usize> = [[bool; COLS]; ROWS];
+
+pub const SCAN_PERIOD_US: u32 = 1_000; // matrix scan period
+pub const MOUSE_SPEED: u32 = 300; // pointer counts per second
@@ -0,0 +1,197 @@
+use core::convert::Infallible;
+
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+use crate::{
+    board::{Board, DiodeDirection},
+    MatrixState,
+};
+
+pub type Column<'a> = &'a dyn InputPin<Error = Infallible>;
+pub type Row<'a> = &'a mut dyn OutputPin<Error = Infallible>;
+
+// the electrical side of the matrix: lines that are driven and lines that are read.
+// with COL2ROW diodes the rows are driven, with ROW2COL the columns are.
+pub trait MatrixIo {
+    // pull output line `index` low while `active`, release it otherwise
+    fn drive(&mut self, index: usize, active: bool);
+    // bit n is set while input line n reads low (switch closed)
+    fn read(&self) -> u32;
+    // wait for the lines to settle after a drive change
+    fn settle(&self) {}
+}
+
+// core cycles at `sys_hz` covering at least `ns`
+pub fn ns_to_cycles(ns: u32, sys_hz: u32) -> u32 {
+    return ((ns as u64 * sys_hz as u64 + 999_999_999) / 1_000_000_000) as u32;
+}
+
+pub fn cycles_to_ns(cycles: u32, sys_hz: u32) -> u32 {
+    return ((cycles as u64 * 1_000_000_000 + sys_hz as u64 - 1) / sys_hz as u64) as u32;
+}
+
+pub fn scan_key_switch<B: Board<ROWS, COLS>, const ROWS: usize, const COLS: usize>(
+    io: &mut impl MatrixIo,
+) -> (MatrixState<ROWS, COLS>, bool) {
+    let mut empty = true;
+    let mut state: MatrixState<ROWS, COLS> = [[false; COLS]; ROWS];
+    let (outputs, inputs) = line_counts::<B, ROWS, COLS>();
+    for output in 0..outputs {
+        io.drive(output, true);
+        io.settle();
+        let bits = io.read();
+        for input in 0..inputs {
+            let pressed = bits & (1 << input) != 0;
+            match B::DIODES {
+                DiodeDirection::Col2Row => state[output][input] = pressed,
+                DiodeDirection::Row2Col => state[input][output] = pressed,
+            }
+        }
+        if bits != 0 {
+            empty = false;
+        }
+        io.drive(output, false);
+        io.settle();
+    }
+    return (state, empty);
+}
+
+// number of driven lines and of read lines
+pub fn line_counts<B: Board<ROWS, COLS>, const ROWS: usize, const COLS: usize>() -> (usize, usize) {
+    return match B::DIODES {
+        DiodeDirection::Col2Row => (ROWS, COLS),
+        DiodeDirection::Row2Col => (COLS, ROWS),
+    };
+}
+
+// matrix behind the embedded-hal pin traits, used to run the scanner off the board.
+// `outputs` are the driven lines, rows for COL2ROW and columns for ROW2COL.
+pub struct PinMatrix<'a, 'p> {
+    pub inputs: &'a [Column<'p>],
+    pub outputs: &'a mut [Row<'p>],
+}
+
+impl<'a, 'p> MatrixIo for PinMatrix<'a, 'p> {
+    fn drive(&mut self, index: usize, active: bool) {
+        if active {
+            self.outputs[index].set_low().unwrap();
+        } else {
+            self.outputs[index].set_high().unwrap();
+        }
+    }
+
+    fn read(&self) -> u32 {
+        let mut bits = 0;
+        for input in 0..self.inputs.len() {
+            if self.inputs[input].is_low().unwrap() {
+                bits |= 1 << input;
+            }
+        }
+        return bits;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use super::*;
+    use crate::{
+        board::{WavierKeys, WavierKeysReversed},
+        sim::keys,
+    };
+
+    struct MockOutput<'a> {
+        low: &'a Cell<bool>,
+    }
+
+    impl OutputPin for MockOutput<'_> {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.low.set(true);
+            return Ok(());
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.low.set(false);
+            return Ok(());
+        }
+    }
+
+    // reads low while a closed switch connects it to a driven output.
+    // bit n of `switches` is the switch to output line n.
+    struct MockInput<'a> {
+        driven: &'a [Cell<bool>],
+        switches: u32,
+    }
+
+    impl InputPin for MockInput<'_> {
+        type Error = Infallible;
+
+        fn is_high(&self) -> Result<bool, Infallible> {
+            return self.is_low().map(|low| !low);
+        }
+
+        fn is_low(&self) -> Result<bool, Infallible> {
+            let low = (0..self.driven.len())
+                .any(|line| self.switches & (1 << line) != 0 && self.driven[line].get());
+            return Ok(low);
+        }
+    }
+
+    // scan with `closed` switches down, also tells if every output was released
+    fn scan<B: Board<5, 15>>(closed: &[(usize, usize)]) -> (MatrixState<5, 15>, bool, bool) {
+        let (outputs, inputs) = line_counts::<B, 5, 15>();
+        let driven: [Cell<bool>; 15] = Default::default();
+        let mut switches = [0u32; 15];
+        for &(row, col) in closed {
+            match B::DIODES {
+                DiodeDirection::Col2Row => switches[col] |= 1 << row,
+                DiodeDirection::Row2Col => switches[row] |= 1 << col,
+            }
+        }
+
+        let mut out_pins: Vec<MockOutput> = driven[..outputs]
+            .iter()
+            .map(|low| MockOutput { low })
+            .collect();
+        let in_pins: Vec<MockInput> = switches[..inputs]
+            .iter()
+            .map(|&switches| MockInput {
+                driven: &driven,
+                switches,
+            })
+            .collect();
+        let mut out_lines: Vec<Row> = out_pins.iter_mut().map(|pin| pin as Row).collect();
+        let in_lines: Vec<Column> = in_pins.iter().map(|pin| pin as Column).collect();
+        let mut io = PinMatrix {
+            inputs: &in_lines,
+            outputs: &mut out_lines,
+        };
+        let (state, empty) = scan_key_switch::<B, 5, 15>(&mut io);
+        let released = driven.iter().all(|low| !low.get());
+        return (state, empty, released);
+    }
+
+    #[test]
+    fn col2row_drives_rows_and_reads_columns() {
+        assert_eq!(line_counts::<WavierKeys, 5, 15>(), (5, 15));
+        let pressed = [(0, 0), (1, 3), (4, 14)];
+        assert_eq!(scan::<WavierKeys>(&pressed), (keys(&pressed), false, true));
+    }
+
+    #[test]
+    fn row2col_drives_columns_and_reads_rows() {
+        assert_eq!(line_counts::<WavierKeysReversed, 5, 15>(), (15, 5));
+        let pressed = [(0, 0), (1, 3), (4, 14)];
+        let expected = (keys(&pressed), false, true);
+        assert_eq!(scan::<WavierKeysReversed>(&pressed), expected);
+    }
+
+    #[test]
+    fn nothing_pressed_is_empty() {
+        assert_eq!(scan::<WavierKeys>(&[]), (keys(&[]), true, true));
+        assert_eq!(scan::<WavierKeysReversed>(&[]), (keys(&[]), true, true));
+    }
+}
@@ -0,0 +1,153 @@
+use crate::{
+    clock::{Duration, Instant},
+    layout::MODE_KEY_POS,
+    pipeline::KeyEvent,
+};
+
+const TAP: Duration = Duration::millis(200); // longest press of the mode key still counted as a tap
+const DOUBLE_TAP: Duration = Duration::millis(300); // second tap within this locks mouse mode
+const MOUSE_IDLE: Duration = Duration::secs(10); // a toggled mouse mode ends after this without input
+
+#[derive(Clone, Copy, PartialEq)]
+enum Latch {
+    Off,
+    Toggled,
+    Locked,
+}
+
+// the key at MODE_KEY_POS: held for momentary mouse mode, tapped to toggle it,
+// double tapped to lock it. a tap while latched goes back to the keyboard.
+pub struct MouseModeKey {
+    latch: Latch,
+    pressed_at: Option<Instant>,
+    // another key went down while the mode key was held, so it is no tap
+    interrupted: bool,
+    last_tap: Option<Instant>,
+    last_input: Instant,
+}
+
+impl MouseModeKey {
+    pub fn new() -> Self {
+        MouseModeKey {
+            latch: Latch::Off,
+            pressed_at: None,
+            interrupted: false,
+            last_tap: None,
+            last_input: Instant::from_ticks(0),
+        }
+    }
+
+    // feed every key event ahead of the keymap
+    pub fn process(&mut self, event: &KeyEvent) {
+        if event.pos == MODE_KEY_POS {
+            if event.pressed {
+                self.pressed_at = Some(event.time);
+                self.interrupted = false;
+            } else if let Some(at) = self.pressed_at.take() {
+                if !self.interrupted && event.time - at <= TAP {
+                    self.tap(event.time);
+                }
+            }
+        } else if event.pressed {
+            self.interrupted = true;
+        }
+        self.last_input = event.time;
+    }
+
+    // call once per scan, `busy` while any key is still held
+    pub fn tick(&mut self, now: Instant, busy: bool) {
+        if busy {
+            self.last_input = now;
+        }
+        if self.latch == Latch::Toggled && now - self.last_input > MOUSE_IDLE {
+            defmt::info!("mouse mode idle, back to keyboard");
+            self.latch = Latch::Off;
+        }
+    }
+
+    // true while mouse mode is on, held or latched
+    pub fn active(&self) -> bool {
+        return self.pressed_at.is_some() || self.latched();
+    }
+
+    pub fn latched(&self) -> bool {
+        return self.latch != Latch::Off;
+    }
+
+    fn tap(&mut self, now: Instant) {
+        let double = self.last_tap.map_or(false, |at| now - at <= DOUBLE_TAP);
+        self.latch = match self.latch {
+            Latch::Off => Latch::Toggled,
+            Latch::Toggled if double => Latch::Locked,
+            _ => Latch::Off,
+        };
+        self.last_tap = Some(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::{Clock, FakeClock},
+        sim::key,
+    };
+
+    // taps the mode key, down for 50ms
+    fn tap(mode: &mut MouseModeKey, clock: &FakeClock) {
+        mode.process(&key(MODE_KEY_POS, true, clock.now()));
+        clock.advance(Duration::millis(50));
+        mode.process(&key(MODE_KEY_POS, false, clock.now()));
+    }
+
+    #[test]
+    fn toggled_mouse_mode_ends_when_idle() {
+        let clock = FakeClock::new();
+        let mut mode = MouseModeKey::new();
+        tap(&mut mode, &clock);
+        assert!(mode.latched());
+        clock.advance(MOUSE_IDLE);
+        mode.tick(clock.now(), false);
+        assert!(mode.active());
+        clock.advance(Duration::millis(1));
+        mode.tick(clock.now(), false);
+        assert!(!mode.active());
+    }
+
+    #[test]
+    fn held_keys_keep_mouse_mode_on() {
+        let clock = FakeClock::new();
+        let mut mode = MouseModeKey::new();
+        tap(&mut mode, &clock);
+        for _ in 0..3 {
+            clock.advance(MOUSE_IDLE);
+            mode.tick(clock.now(), true);
+        }
+        clock.advance(MOUSE_IDLE);
+        mode.tick(clock.now(), false);
+        assert!(mode.latched());
+    }
+
+    #[test]
+    fn locked_mouse_mode_outlasts_the_idle_time() {
+        let clock = FakeClock::new();
+        let mut mode = MouseModeKey::new();
+        tap(&mut mode, &clock);
+        clock.advance(Duration::millis(100));
+        tap(&mut mode, &clock);
+        clock.advance(MOUSE_IDLE * 3);
+        mode.tick(clock.now(), false);
+        assert!(mode.latched());
+    }
+
+    #[test]
+    fn long_press_is_no_tap() {
+        let clock = FakeClock::new();
+        let mut mode = MouseModeKey::new();
+        mode.process(&key(MODE_KEY_POS, true, clock.now()));
+        assert!(mode.active());
+        clock.advance(TAP + Duration::millis(1));
+        mode.process(&key(MODE_KEY_POS, false, clock.now()));
+        assert!(!mode.active());
+    }
+}
@@ -0,0 +1,54 @@
+use crate::{
+    action::Action,
+    held::Layer,
+    keycodes::{KeyCodes, ModifierMasks},
+};
+
+pub const SHIFT: u8 = ModifierMasks::LeftShift as u8 | ModifierMasks::RightShift as u8;
+
+// `key` pressed while any of `mods` is held by a modifier key sends
+// `replacement` instead, with `mods` left out of the report. that lasts until
+// the key is released or another key goes down.
+#[derive(Clone, Copy)]
+pub struct KeyOverride {
+    pub mods: u8,
+    pub key: KeyCodes,
+    pub replacement: Action,
+    // only keys pressed on this layer are replaced, None for every layer
+    pub layer: Option<Layer>,
+}
+
+impl KeyOverride {
+    pub const fn new(mods: u8, key: KeyCodes, replacement: Action) -> Self {
+        KeyOverride {
+            mods,
+            key,
+            replacement,
+            layer: None,
+        }
+    }
+
+    pub const fn on(self, layer: Layer) -> Self {
+        KeyOverride {
+            layer: Some(layer),
+            ..self
+        }
+    }
+}
+
+// first rule replacing `action` pressed on `layer` while `held_mods` are down
+pub fn find_override(
+    rules: &[KeyOverride],
+    action: Action,
+    layer: Layer,
+    held_mods: u8,
+) -> Option<&KeyOverride> {
+    let Action::Key(code) = action else {
+        return None;
+    };
+    return rules.iter().find(|rule| {
+        rule.key == code
+            && rule.mods & held_mods != 0
+            && rule.layer.map_or(true, |only| only == layer)
+    });
+}
@@ -0,0 +1,156 @@
+use core::marker::PhantomData;
+
+use pio::{InSource, SetDestination};
+use rp_pico::hal::{
+    pac,
+    pio::{
+        Buffers, PIOBuilder, PIOExt, PinDir, Running, Rx, ShiftDirection, StateMachine, PIO, SM0,
+    },
+};
+
+use crate::{
+    board::{Board, DiodeDirection},
+    sio_matrix::{configure_pins, FUNCSEL_PIO0},
+    MatrixState,
+};
+
+// the state machine runs one instruction per microsecond
+const PIO_HZ: u32 = 1_000_000;
+
+// pins from the lowest to the highest one in `pins`
+const fn span(pins: &[u8]) -> u8 {
+    let (mut min, mut max) = (u8::MAX, 0);
+    let mut i = 0;
+    while i < pins.len() {
+        if pins[i] < min {
+            min = pins[i];
+        }
+        if pins[i] > max {
+            max = pins[i];
+        }
+        i += 1;
+    }
+    return max - min + 1;
+}
+
+pub struct PioScanner<B: Board<ROWS, COLS>, const ROWS: usize, const COLS: usize> {
+    _pio: PIO<pac::PIO0>,
+    _sm: StateMachine<(pac::PIO0, SM0), Running>,
+    rx: Rx<(pac::PIO0, SM0)>,
+    col_base: u8,
+    col_span: u8,
+    rows: [u32; ROWS], // latest column bits per row, set while pressed
+    _board: PhantomData<B>,
+}
+
+impl<B: Board<ROWS, COLS>, const ROWS: usize, const COLS: usize> PioScanner<B, ROWS, COLS> {
+    // evaluated when the scanner is built for a board, so a board the program
+    // can't scan fails the build instead of the boot
+    const SUPPORTED: () = {
+        // `set pins` drives every pin from row_base on, so the rows have to sit on
+        // consecutive pins with nothing else in between. it reaches 5 pins, six
+        // instructions per row have to fit in 32, and the row tag takes 3 bits of
+        // every pushed word
+        assert!(
+            span(&B::ROW_PINS) as usize == ROWS && ROWS <= 5,
+            "pio-scan needs at most 5 rows on consecutive pins"
+        );
+        assert!(
+            span(&B::COL_PINS) <= 29 && COLS <= 32,
+            "pio-scan needs the columns within 29 pins"
+        );
+        // the program drives rows, ROW2COL boards have to use the cpu scanner
+        assert!(
+            matches!(B::DIODES, DiodeDirection::Col2Row),
+            "pio-scan can't scan ROW2COL diodes"
+        );
+    };
+
+    pub fn new(pio0: pac::PIO0, resets: &mut pac::RESETS, sys_hz: u32) -> Self {
+        let row_base = *B::ROW_PINS.iter().min().unwrap();
+        let row_span = *B::ROW_PINS.iter().max().unwrap() - row_base + 1;
+        let col_base = *B::COL_PINS.iter().min().unwrap();
+        let col_span = *B::COL_PINS.iter().max().unwrap() - col_base + 1;
+        let () = Self::SUPPORTED;
+
+        // settle delay in pio cycles on top of the instruction itself
+        let delay = (B::SETTLE_NS + 999) / 1000;
+        let delay = delay.saturating_sub(1).min(31) as u8;
+
+        // every row is pushed as (columns << 3) | row, so the cpu can pick the
+        // words up in any order and never mixes two rows up
+        let idle = (1 << row_span) - 1;
+        let mut a = pio::Assembler::<{ pio::RP2040_MAX_PROGRAM_SIZE }>::new();
+        let mut wrap_target = a.label();
+        let mut wrap_source = a.label();
+        a.bind(&mut wrap_target);
+        for row in 0..ROWS {
+            let active = idle & !(1 << (B::ROW_PINS[row] - row_base));
+            a.set_with_delay(SetDestination::PINS, active, delay);
+            a.r#in(InSource::PINS, col_span);
+            a.set(SetDestination::X, row as u8);
+            a.r#in(InSource::X, 3);
+            a.push(false, false);
+            a.set_with_delay(SetDestination::PINS, idle, delay);
+        }
+        a.bind(&mut wrap_source);
+        let program = a.assemble_with_wrap(wrap_source, wrap_target);
+
+        configure_pins::<B, ROWS, COLS>(FUNCSEL_PIO0);
+        let (mut pio, sm0, _, _, _) = pio0.split(resets);
+        let installed = pio.install(&program).unwrap();
+        let (mut sm, rx, _) = PIOBuilder::from_program(installed)
+            .set_pins(row_base, row_span)
+            .in_pin_base(col_base)
+            .in_shift_direction(ShiftDirection::Left)
+            .autopush(false)
+            .buffers(Buffers::OnlyRx)
+            .clock_divisor_fixed_point((sys_hz / PIO_HZ) as u16, 0)
+            .build(sm0);
+        sm.set_pindirs((row_base..row_base + row_span).map(|pin| (pin, PinDir::Output)));
+
+        PioScanner {
+            _pio: pio,
+            _sm: sm.start(),
+            rx,
+            col_base,
+            col_span,
+            rows: [0; ROWS],
+            _board: PhantomData,
+        }
+    }
+
+    // drain the rx fifo, returns true if any row changed
+    pub fn poll(&mut self) -> bool {
+        let mut changed = false;
+        while let Some(word) = self.rx.read() {
+            let row = (word & 0b111) as usize;
+            let pressed = !(word >> 3) & ((1 << self.col_span) - 1);
+            let mut bits = 0;
+            for (col, &pin) in B::COL_PINS.iter().enumerate() {
+                if pressed & (1 << (pin - self.col_base)) != 0 {
+                    bits |= 1 << col;
+                }
+            }
+            if row < ROWS && self.rows[row] != bits {
+                self.rows[row] = bits;
+                changed = true;
+            }
+        }
+        return changed;
+    }
+
+    pub fn state(&self) -> (MatrixState<ROWS, COLS>, bool) {
+        let mut empty = true;
+        let mut state: MatrixState<ROWS, COLS> = [[false; COLS]; ROWS];
+        for row in 0..ROWS {
+            for col in 0..COLS {
+                state[row][col] = self.rows[row] & (1 << col) != 0;
+            }
+            if self.rows[row] != 0 {
+                empty = false;
+            }
+        }
+        return (state, empty);
+    }
+}
@@ -0,0 +1,193 @@
+// key handling runs as a chain of stages passing timestamped events along:
+//
+//   scan -> Debounce -> Combo -> KeymapStage -> ActionStage.. -> ActionProcessor -> reports
+//
+// the action stages run in this order: SpaceCadet -> AutoShift -> CapsWord.
+//
+// every stage only sees the events of the one before it, so features that
+// depend on timing (tap-hold, combos, macros) can hold events back or emit
+// new ones without the later stages knowing. times come from the board clock.
+
+use crate::{action::Action, clock::Instant, held::Layer, layout::KeyPos, MatrixState};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyEvent {
+    pub pos: KeyPos,
+    pub pressed: bool,
+    pub time: Instant,
+}
+
+// a key event together with the action the keymap resolved it to and the
+// layer it came from. the release of a key carries the same as its press.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ActionEvent {
+    pub key: KeyEvent,
+    pub action: Action,
+    pub layer: Layer,
+}
+
+// first stage: turns matrix snapshots into key events. it decides when a
+// change of the raw matrix is real and emits one event per accepted change.
+pub trait Debounce<const ROWS: usize, const COLS: usize> {
+    fn update(
+        &mut self,
+        raw: &MatrixState<ROWS, COLS>,
+        time: Instant,
+        emit: &mut impl FnMut(KeyEvent),
+    );
+}
+
+// second stage: may hold events back, merge several presses into one or emit
+// events of its own. `tick` runs once per scan so held back events can time out.
+pub trait Combo {
+    fn process(&mut self, event: KeyEvent, emit: &mut impl FnMut(KeyEvent));
+    fn tick(&mut self, _time: Instant, _emit: &mut impl FnMut(KeyEvent)) {}
+}
+
+// third stage: resolves key events to actions. keys that only change how
+// other keys resolve (layer and mode keys) may resolve to nothing.
+pub trait KeymapStage {
+    fn process(&mut self, event: KeyEvent) -> Option<ActionEvent>;
+    fn tick(&mut self, _time: Instant) {}
+}
+
+// fourth stage, any number of them in a row: reworks resolved actions. like a
+// combo it may hold events back or emit new ones and `tick` runs once per scan.
+pub trait ActionStage {
+    fn process(&mut self, event: ActionEvent, emit: &mut impl FnMut(ActionEvent));
+    fn tick(&mut self, _time: Instant, _emit: &mut impl FnMut(ActionEvent)) {}
+}
+
+// last stage: keeps track of the held actions and builds reports from them
+pub trait ActionProcessor {
+    fn process(&mut self, event: ActionEvent);
+}
+
+// release of a key whose press was only just emitted. reports are built once
+// per scan, so a press and release in the same scan would never reach the host.
+// the release is held back until a later scan.
+pub struct DeferredRelease {
+    event: Option<ActionEvent>,
+}
+
+impl DeferredRelease {
+    pub fn new() -> Self {
+        DeferredRelease { event: None }
+    }
+
+    pub fn defer(&mut self, event: ActionEvent, emit: &mut impl FnMut(ActionEvent)) {
+        // only one is kept, an earlier one goes out now rather than never
+        if let Some(earlier) = self.event.replace(event) {
+            emit(earlier);
+        }
+    }
+
+    // call with every event time and once per scan
+    pub fn flush(&mut self, now: Instant, emit: &mut impl FnMut(ActionEvent)) {
+        if let Some(event) = self.event {
+            if now > event.key.time {
+                self.event = None;
+                emit(event);
+            }
+        }
+    }
+}
+
+// emits every change between two snapshots, for input core1 already
+// debounced in dual core builds. raw scans go through debounce::Debouncer.
+pub struct Edges<const ROWS: usize, const COLS: usize> {
+    last: MatrixState<ROWS, COLS>,
+}
+
+impl<const ROWS: usize, const COLS: usize> Edges<ROWS, COLS> {
+    pub fn new() -> Self {
+        Edges {
+            last: [[false; COLS]; ROWS],
+        }
+    }
+}
+
+impl<const ROWS: usize, const COLS: usize> Debounce<ROWS, COLS> for Edges<ROWS, COLS> {
+    fn update(
+        &mut self,
+        raw: &MatrixState<ROWS, COLS>,
+        time: Instant,
+        emit: &mut impl FnMut(KeyEvent),
+    ) {
+        for row in 0..ROWS {
+            for col in 0..COLS {
+                if raw[row][col] != self.last[row][col] {
+                    self.last[row][col] = raw[row][col];
+                    emit(KeyEvent {
+                        pos: KeyPos { row, col },
+                        pressed: raw[row][col],
+                        time,
+                    });
+                }
+            }
+        }
+    }
+}
+
+// combo stage without any combos
+pub struct NoCombos;
+
+impl Combo for NoCombos {
+    fn process(&mut self, event: KeyEvent, emit: &mut impl FnMut(KeyEvent)) {
+        emit(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        keycodes::KeyCodes,
+        layout::pos,
+        sim::{at, key},
+    };
+
+    #[test]
+    fn edges_emit_every_change_once() {
+        let mut edges = Edges::<2, 3>::new();
+        let mut events = Vec::new();
+        let mut raw = [[false; 3]; 2];
+        raw[1][2] = true;
+        edges.update(&raw, at(1), &mut |event| events.push(event));
+        edges.update(&raw, at(2), &mut |event| events.push(event));
+        raw[1][2] = false;
+        raw[0][0] = true;
+        edges.update(&raw, at(3), &mut |event| events.push(event));
+        let expected = [
+            key(pos(1, 2), true, at(1)),
+            key(pos(0, 0), true, at(3)),
+            key(pos(1, 2), false, at(3)),
+        ];
+        assert_eq!(events, expected);
+    }
+
+    #[test]
+    fn no_combos_passes_events_through() {
+        let mut events = Vec::new();
+        NoCombos.process(key(pos(0, 1), true, at(1)), &mut |event| events.push(event));
+        NoCombos.tick(at(2), &mut |event| events.push(event));
+        assert_eq!(events, [key(pos(0, 1), true, at(1))]);
+    }
+
+    #[test]
+    fn deferred_release_goes_out_on_a_later_scan() {
+        let release = ActionEvent {
+            key: key(pos(2, 1), false, at(5)),
+            action: Action::Key(KeyCodes::KeyA),
+            layer: Layer::Base,
+        };
+        let mut deferred = DeferredRelease::new();
+        let mut events = Vec::new();
+        deferred.defer(release, &mut |event| events.push(event));
+        deferred.flush(at(5), &mut |event| events.push(event));
+        assert!(events.is_empty());
+        deferred.flush(at(6), &mut |event| events.push(event));
+        deferred.flush(at(7), &mut |event| events.push(event));
+        assert_eq!(events, [release]);
+    }
+}
@@ -0,0 +1,426 @@
+use heapless::Vec;
+use usbd_hid::descriptor::{KeyboardReport, MouseReport};
+
+use crate::{
+    action::{Action, MouseAction},
+    clock::Instant,
+    keycodes::KeyCodes,
+    layout::KeyPos,
+    overrides::{find_override, KeyOverride},
+    pipeline::{ActionEvent, ActionProcessor},
+    slots::KeySlots,
+    MOUSE_SPEED,
+};
+
+const KEYS_HELD: usize = 16; // actions held at once, further presses are dropped
+const MAX_STEP_US: u64 = 100_000; // longest gap between reports the pointer catches up on
+
+pub const NO_KEYS: KeyboardReport = KeyboardReport {
+    modifier: 0,
+    reserved: 0,
+    leds: 0,
+    keycodes: [0; 6],
+};
+pub const NO_BUTTONS: MouseReport = MouseReport {
+    buttons: 0,
+    x: 0,
+    y: 0,
+    wheel: 0,
+    pan: 0,
+};
+
+// sub-count pointer movement, in counts * 1_000_000 (counts per second * us)
+struct MouseMotion {
+    x: i32,
+    y: i32,
+}
+
+// an action whose key is down
+#[derive(Clone, Copy)]
+struct Held {
+    pos: KeyPos,
+    // after the key overrides, fixed when the key went down
+    action: Action,
+    // modifiers that triggered its override, the host must not see them
+    suppressed: u8,
+    // kept out of the keyboard report until the key is released
+    muted: bool,
+}
+
+// keycode sent together with its own modifiers
+fn is_modified(action: Action) -> bool {
+    return matches!(action, Action::ModKey(_, code) if code != KeyCodes::Reserved);
+}
+
+// keycode sent with the modifier keys held, if any
+fn is_plain(action: Action) -> bool {
+    return matches!(action, Action::Key(code)
+        if code.modifier_mask().is_none() && code != KeyCodes::Reserved);
+}
+
+// action processor building both hid reports from the held actions
+pub struct Reports {
+    // in press order
+    held: Vec<Held, KEYS_HELD>,
+    overrides: &'static [KeyOverride],
+    slots: KeySlots,
+    motion: MouseMotion,
+    last_mouse_report: Option<Instant>,
+}
+
+impl Reports {
+    pub fn new(overrides: &'static [KeyOverride]) -> Self {
+        Reports {
+            held: Vec::new(),
+            overrides,
+            slots: KeySlots::new(),
+            motion: MouseMotion { x: 0, y: 0 },
+            last_mouse_report: None,
+        }
+    }
+
+    // modifiers held by modifier keys
+    fn modifiers(&self) -> u8 {
+        let mut modif = 0;
+        for held in self.held.iter() {
+            match held.action {
+                Action::Key(code) => modif |= code.modifier_mask().unwrap_or(0),
+                Action::ModKey(mods, KeyCodes::Reserved) => modif |= mods,
+                _ => {}
+            }
+        }
+        return modif;
+    }
+
+    pub fn keyboard_report(&mut self) -> KeyboardReport {
+        let mut modif = self.modifiers();
+        let mut plain: Vec<u8, KEYS_HELD> = Vec::new();
+        // the latest modified keycode and its modifiers
+        let mut modified = None;
+        let mut suppressed = 0;
+
+        for held in self.held.iter().filter(|held| !held.muted) {
+            suppressed |= held.suppressed;
+            let action = held.action;
+            match action {
+                Action::Key(code) if is_plain(action) => {
+                    plain.push(code as u8).ok();
+                }
+                Action::ModKey(mods, code) if is_modified(action) => {
+                    modified = Some((mods, code as u8));
+                }
+                Action::Key(_) | Action::ModKey(..) => {}
+                // turned into plain modifiers and keycodes by the space cadet stage
+                Action::Mouse(_) | Action::CapsWord | Action::SpaceCadet(..) => {}
+            }
+        }
+
+        // the host applies the modifier byte to every keycode in the report, so
+        // a modified keycode goes out alone
+        modif &= !suppressed;
+        let keycodes = match modified {
+            Some((mods, code)) => {
+                modif |= mods;
+                self.slots.assign(&[code])
+            }
+            None => self.slots.assign(&plain),
+        };
+
+        KeyboardReport {
+            modifier: modif,
+            reserved: 0,
+            leds: 0,
+            keycodes,
+        }
+    }
+
+    pub fn mouse_report(&mut self, now: Instant) -> MouseReport {
+        let mut report = NO_BUTTONS;
+        // movement covers the time since the previous report
+        let elapsed_us = self
+            .last_mouse_report
+            .map_or(0, |last| (now - last).to_micros().min(MAX_STEP_US));
+        self.last_mouse_report = Some(now);
+        let step = (MOUSE_SPEED as u64 * elapsed_us) as i32;
+        let mut dx = 0;
+        let mut dy = 0;
+        for held in self.held.iter() {
+            match held.action {
+                Action::Mouse(MouseAction::LeftButton) => report.buttons |= 1 << 0,
+                Action::Mouse(MouseAction::RightButton) => report.buttons |= 1 << 1,
+                Action::Mouse(MouseAction::MoveLeft) => dx -= step,
+                Action::Mouse(MouseAction::MoveRight) => dx += step,
+                Action::Mouse(MouseAction::MoveUp) => dy -= step,
+                Action::Mouse(MouseAction::MoveDown) => dy += step,
+                _ => {}
+            }
+        }
+
+        // drop leftover sub-count movement once the direction keys are released
+        let motion = &mut self.motion;
+        motion.x = if dx == 0 { 0 } else { motion.x + dx };
+        motion.y = if dy == 0 { 0 } else { motion.y + dy };
+        report.x = (motion.x / 1_000_000).clamp(-127, 127) as i8;
+        report.y = (motion.y / 1_000_000).clamp(-127, 127) as i8;
+
+        return report;
+    }
+
+    // the host took `report`, keep only the movement it did not carry
+    pub fn mouse_sent(&mut self, report: &MouseReport) {
+        self.motion.x -= report.x as i32 * 1_000_000;
+        self.motion.y -= report.y as i32 * 1_000_000;
+    }
+}
+
+impl ActionProcessor for Reports {
+    fn process(&mut self, event: ActionEvent) {
+        if !event.key.pressed {
+            self.held.retain(|held| held.pos != event.key.pos);
+            return;
+        }
+        // overrides are decided once, with the modifiers held right now.
+        // releasing them early must not turn the key into a new press.
+        let modif = self.modifiers();
+        let (action, suppressed) =
+            match find_override(self.overrides, event.action, event.layer, modif) {
+                Some(rule) => (rule.replacement, rule.mods & modif),
+                None => (event.action, 0),
+            };
+        // a modified keycode can't share a report with other keycodes, so of the
+        // two the one pressed later wins. the other stays muted until released,
+        // letting it back in would look like a second press to the host.
+        // an overridden key ends the same way, so the new key gets the
+        // modifiers the override held back.
+        if is_modified(action) || is_plain(action) {
+            for held in self.held.iter_mut() {
+                if is_modified(held.action)
+                    || (is_modified(action) && is_plain(held.action))
+                    || held.suppressed != 0
+                {
+                    held.muted = true;
+                }
+            }
+        }
+        let held = Held {
+            pos: event.key.pos,
+            action,
+            suppressed,
+            muted: false,
+        };
+        self.held.push(held).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        action::{LCTL, LSFT},
+        held::Layer,
+        keycodes::KeyCodes::*,
+        layout::{pos, KEY_OVERRIDES},
+        overrides::SHIFT,
+        sim::{at, event},
+    };
+
+    #[test]
+    fn keys_and_modifiers() {
+        let mut reports = Reports::new(&[]);
+        reports.process(event(pos(0, 0), true, Action::Key(LeftShift), at(0)));
+        reports.process(event(pos(0, 1), true, Action::Key(KeyA), at(0)));
+        reports.process(event(pos(0, 2), true, Action::Key(KeyB), at(0)));
+        let report = reports.keyboard_report();
+        assert_eq!(report.modifier, 0x02);
+        assert_eq!(report.keycodes, [KeyA as u8, KeyB as u8, 0, 0, 0, 0]);
+
+        reports.process(event(pos(0, 0), false, Action::Key(LeftShift), at(0)));
+        reports.process(event(pos(0, 1), false, Action::Key(KeyA), at(0)));
+        let report = reports.keyboard_report();
+        assert_eq!(report.modifier, 0);
+        assert_eq!(report.keycodes, [0, KeyB as u8, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn keycodes_go_out_in_press_order() {
+        let mut reports = Reports::new(&[]);
+        reports.process(event(pos(0, 9), true, Action::Key(KeyO), at(0)));
+        reports.process(event(pos(0, 2), true, Action::Key(KeyW), at(0)));
+        assert_eq!(
+            reports.keyboard_report().keycodes,
+            [KeyO as u8, KeyW as u8, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn modified_key_carries_its_modifiers() {
+        let mut reports = Reports::new(&[]);
+        reports.process(event(pos(0, 0), true, LCTL(KeyC), at(0)));
+        let report = reports.keyboard_report();
+        assert_eq!(report.modifier, 0x01);
+        assert_eq!(report.keycodes, [KeyC as u8, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn key_left_out_for_a_modified_key_stays_out() {
+        let mut reports = Reports::new(&[]);
+        reports.process(event(pos(0, 0), true, Action::Key(KeyA), at(0)));
+        assert_eq!(
+            reports.keyboard_report().keycodes,
+            [KeyA as u8, 0, 0, 0, 0, 0]
+        );
+        reports.process(event(pos(0, 1), true, LCTL(KeyC), at(0)));
+        let report = reports.keyboard_report();
+        assert_eq!(report.modifier, 0x01);
+        assert_eq!(report.keycodes, [KeyC as u8, 0, 0, 0, 0, 0]);
+        // a is still held but must not be typed a second time
+        reports.process(event(pos(0, 1), false, LCTL(KeyC), at(0)));
+        let report = reports.keyboard_report();
+        assert_eq!(report.modifier, 0);
+        assert_eq!(report.keycodes, [0; 6]);
+        reports.process(event(pos(0, 0), false, Action::Key(KeyA), at(0)));
+        reports.process(event(pos(0, 0), true, Action::Key(KeyA), at(0)));
+        assert_eq!(
+            reports.keyboard_report().keycodes,
+            [KeyA as u8, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn key_pressed_after_a_modified_key_replaces_it() {
+        let mut reports = Reports::new(&[]);
+        reports.process(event(pos(0, 0), true, LSFT(Key9), at(0)));
+        reports.process(event(pos(0, 1), true, Action::Key(KeyA), at(0)));
+        let report = reports.keyboard_report();
+        assert_eq!(report.modifier, 0);
+        assert_eq!(report.keycodes, [KeyA as u8, 0, 0, 0, 0, 0]);
+        reports.process(event(pos(0, 1), false, Action::Key(KeyA), at(0)));
+        assert_eq!(reports.keyboard_report().keycodes, [0; 6]);
+    }
+
+    #[test]
+    fn modified_keys_keep_their_modifiers_to_themselves() {
+        let mut reports = Reports::new(&[]);
+        reports.process(event(pos(0, 0), true, LSFT(Key9), at(0)));
+        reports.process(event(pos(0, 1), true, LCTL(KeyC), at(0)));
+        let report = reports.keyboard_report();
+        assert_eq!(report.modifier, 0x01);
+        assert_eq!(report.keycodes, [KeyC as u8, 0, 0, 0, 0, 0]);
+        reports.process(event(pos(0, 1), false, LCTL(KeyC), at(0)));
+        let report = reports.keyboard_report();
+        assert_eq!(report.modifier, 0);
+        assert_eq!(report.keycodes, [0; 6]);
+    }
+
+    #[test]
+    fn modifier_keys_apply_to_modified_keys() {
+        let mut reports = Reports::new(&[]);
+        reports.process(event(pos(0, 0), true, Action::Key(LeftAlt), at(0)));
+        reports.process(event(pos(0, 1), true, LCTL(KeyC), at(0)));
+        let report = reports.keyboard_report();
+        assert_eq!(report.modifier, 0x05);
+        assert_eq!(report.keycodes, [KeyC as u8, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn mouse_buttons_and_motion() {
+        let mut reports = Reports::new(&[]);
+        reports.process(event(
+            pos(0, 0),
+            true,
+            Action::Mouse(MouseAction::LeftButton),
+            at(0),
+        ));
+        reports.process(event(
+            pos(0, 1),
+            true,
+            Action::Mouse(MouseAction::MoveRight),
+            at(0),
+        ));
+        reports.mouse_report(at(0));
+        let report = reports.mouse_report(at(100));
+        reports.mouse_sent(&report);
+        assert_eq!(report.buttons, 1);
+        assert_eq!(report.x as u32, MOUSE_SPEED / 10);
+        assert_eq!(report.y, 0);
+        // no keycodes for mouse actions
+        assert_eq!(reports.keyboard_report().keycodes, [0; 6]);
+    }
+
+    #[test]
+    fn override_is_fixed_when_the_key_goes_down() {
+        const OVERRIDES: [KeyOverride; 1] =
+            [KeyOverride::new(SHIFT, BackSpace, Action::Key(Delete))];
+        let mut reports = Reports::new(&OVERRIDES);
+        reports.process(event(pos(0, 0), true, Action::Key(LeftShift), at(0)));
+        reports.process(event(pos(0, 1), true, Action::Key(BackSpace), at(0)));
+        let report = reports.keyboard_report();
+        assert_eq!(report.modifier, 0);
+        assert_eq!(report.keycodes, [Delete as u8, 0, 0, 0, 0, 0]);
+        // letting go of shift first keeps deleting forward
+        reports.process(event(pos(0, 0), false, Action::Key(LeftShift), at(0)));
+        let report = reports.keyboard_report();
+        assert_eq!(report.modifier, 0);
+        assert_eq!(report.keycodes, [Delete as u8, 0, 0, 0, 0, 0]);
+        reports.process(event(pos(0, 1), false, Action::Key(BackSpace), at(0)));
+        // shift pressed while backspace is down does not replace it either
+        reports.process(event(pos(0, 1), true, Action::Key(BackSpace), at(0)));
+        reports.process(event(pos(0, 0), true, Action::Key(LeftShift), at(0)));
+        let report = reports.keyboard_report();
+        assert_eq!(report.modifier, 0x02);
+        assert_eq!(report.keycodes, [BackSpace as u8, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn key_pressed_during_an_override_ends_it() {
+        let mut reports = Reports::new(&KEY_OVERRIDES);
+        reports.process(event(pos(0, 0), true, Action::Key(LeftShift), at(0)));
+        reports.process(event(pos(0, 1), true, Action::Key(BackSpace), at(0)));
+        assert_eq!(
+            reports.keyboard_report().keycodes,
+            [Delete as u8, 0, 0, 0, 0, 0]
+        );
+        reports.process(event(pos(0, 2), true, Action::Key(KeyA), at(0)));
+        let report = reports.keyboard_report();
+        assert_eq!(report.modifier, 0x02);
+        assert_eq!(report.keycodes, [KeyA as u8, 0, 0, 0, 0, 0]);
+        // backspace stays out until it is pressed again
+        reports.process(event(pos(0, 2), false, Action::Key(KeyA), at(0)));
+        let report = reports.keyboard_report();
+        assert_eq!(report.modifier, 0x02);
+        assert_eq!(report.keycodes, [0; 6]);
+    }
+
+    #[test]
+    fn shift_escape_types_a_tilde() {
+        let mut reports = Reports::new(&KEY_OVERRIDES);
+        reports.process(event(pos(0, 0), true, Action::Key(RightShift), at(0)));
+        reports.process(event(pos(0, 1), true, Action::Key(Escape), at(0)));
+        let report = reports.keyboard_report();
+        assert_eq!(report.modifier, 0x02);
+        assert_eq!(report.keycodes, [Grave as u8, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn layer_bound_override_skips_other_layers() {
+        let mut reports = Reports::new(&KEY_OVERRIDES);
+        reports.process(event(pos(0, 0), true, Action::Key(LeftShift), at(0)));
+        let escape = ActionEvent {
+            layer: Layer::Fn,
+            ..event(pos(0, 1), true, Action::Key(Escape), at(0))
+        };
+        reports.process(escape);
+        let report = reports.keyboard_report();
+        assert_eq!(report.modifier, 0x02);
+        assert_eq!(report.keycodes, [Escape as u8, 0, 0, 0, 0, 0]);
+        reports.process(event(pos(0, 1), false, Action::Key(Escape), at(0)));
+        // backspace has no layer and is replaced on fn too
+        reports.process(ActionEvent {
+            layer: Layer::Fn,
+            ..event(pos(0, 2), true, Action::Key(BackSpace), at(0))
+        });
+        let report = reports.keyboard_report();
+        assert_eq!(report.modifier, 0);
+        assert_eq!(report.keycodes, [Delete as u8, 0, 0, 0, 0, 0]);
+    }
+}
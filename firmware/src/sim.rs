@@ -0,0 +1,308 @@
+use core::{cell::Cell, convert::Infallible};
+
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+use heapless::Vec;
+
+use crate::{
+    action::Action,
+    board::{Board, DiodeDirection},
+    clock::Instant,
+    held::Layer,
+    layout::KeyPos,
+    matrix::{scan_key_switch, Column, PinMatrix, Row},
+    pipeline::{ActionEvent, KeyEvent},
+    MatrixState,
+};
+
+// simulated key matrix, so the scanner can be exercised without a board.
+// every line is pulled up and reads low when it is driven low or connected to
+// a low line through closed switches. diodes only pass current one way,
+// crosstalk drags the neighbours of a low input line down with it.
+pub struct SimMatrix<const ROWS: usize, const COLS: usize> {
+    pub direction: DiodeDirection,
+    pub diodes: bool, // false for a hand-wired matrix without diodes
+    pub crosstalk: bool,
+    pressed: Cell<MatrixState<ROWS, COLS>>,
+    rows_driven: Cell<u32>,
+    cols_driven: Cell<u32>,
+}
+
+#[derive(Clone, Copy)]
+enum Line {
+    Row(usize),
+    Col(usize),
+}
+
+pub struct SimPin<'a, const ROWS: usize, const COLS: usize> {
+    matrix: &'a SimMatrix<ROWS, COLS>,
+    line: Line,
+}
+
+impl<const ROWS: usize, const COLS: usize> SimMatrix<ROWS, COLS> {
+    pub fn new(direction: DiodeDirection) -> Self {
+        SimMatrix {
+            direction,
+            diodes: true,
+            crosstalk: false,
+            pressed: Cell::new([[false; COLS]; ROWS]),
+            rows_driven: Cell::new(0),
+            cols_driven: Cell::new(0),
+        }
+    }
+
+    pub fn press(&self, row: usize, col: usize) {
+        let mut pressed = self.pressed.get();
+        pressed[row][col] = true;
+        self.pressed.set(pressed);
+    }
+
+    pub fn release(&self, row: usize, col: usize) {
+        let mut pressed = self.pressed.get();
+        pressed[row][col] = false;
+        self.pressed.set(pressed);
+    }
+
+    // run the real scanner against the simulated pins
+    pub fn scan<B: Board<ROWS, COLS>>(&self) -> (MatrixState<ROWS, COLS>, bool) {
+        let (out_lines, in_lines, out_line, in_line): (_, _, fn(usize) -> Line, fn(usize) -> Line) =
+            match B::DIODES {
+                DiodeDirection::Col2Row => (ROWS, COLS, Line::Row, Line::Col),
+                DiodeDirection::Row2Col => (COLS, ROWS, Line::Col, Line::Row),
+            };
+        let mut out_pins: Vec<SimPin<ROWS, COLS>, 32> = (0..out_lines)
+            .map(|index| SimPin {
+                matrix: self,
+                line: out_line(index),
+            })
+            .collect();
+        let in_pins: Vec<SimPin<ROWS, COLS>, 32> = (0..in_lines)
+            .map(|index| SimPin {
+                matrix: self,
+                line: in_line(index),
+            })
+            .collect();
+
+        let mut outputs: Vec<Row, 32> = Vec::new();
+        for pin in out_pins.iter_mut() {
+            outputs.push(pin).ok();
+        }
+        let mut inputs: Vec<Column, 32> = Vec::new();
+        for pin in in_pins.iter() {
+            inputs.push(pin).ok();
+        }
+        let mut io = PinMatrix {
+            inputs: &inputs,
+            outputs: &mut outputs,
+        };
+        return scan_key_switch::<B, ROWS, COLS>(&mut io);
+    }
+
+    // row and column lines reading low, as bitmasks
+    fn levels(&self) -> (u32, u32) {
+        let pressed = self.pressed.get();
+        let mut rows = self.rows_driven.get();
+        let mut cols = self.cols_driven.get();
+        if self.diodes {
+            for row in 0..ROWS {
+                for col in 0..COLS {
+                    if !pressed[row][col] {
+                        continue;
+                    }
+                    match self.direction {
+                        DiodeDirection::Col2Row if rows & (1 << row) != 0 => cols |= 1 << col,
+                        DiodeDirection::Row2Col if cols & (1 << col) != 0 => rows |= 1 << row,
+                        _ => {}
+                    }
+                }
+            }
+        } else {
+            // without diodes a low level spreads through every chain of closed switches
+            let mut changed = true;
+            while changed {
+                changed = false;
+                for row in 0..ROWS {
+                    for col in 0..COLS {
+                        let row_low = rows & (1 << row) != 0;
+                        let col_low = cols & (1 << col) != 0;
+                        if pressed[row][col] && row_low != col_low {
+                            rows |= 1 << row;
+                            cols |= 1 << col;
+                            changed = true;
+                        }
+                    }
+                }
+            }
+        }
+        if self.crosstalk {
+            match self.direction {
+                DiodeDirection::Col2Row => cols = spread(cols, COLS),
+                DiodeDirection::Row2Col => rows = spread(rows, ROWS),
+            }
+        }
+        return (rows, cols);
+    }
+
+    fn set_driven(&self, line: Line, low: bool) {
+        let (driven, bit) = match line {
+            Line::Row(row) => (&self.rows_driven, 1 << row),
+            Line::Col(col) => (&self.cols_driven, 1 << col),
+        };
+        if low {
+            driven.set(driven.get() | bit);
+        } else {
+            driven.set(driven.get() & !bit);
+        }
+    }
+}
+
+// matrix state with only `pressed` down, to compare scans against
+pub fn keys<const ROWS: usize, const COLS: usize>(
+    pressed: &[(usize, usize)],
+) -> MatrixState<ROWS, COLS> {
+    let mut state = [[false; COLS]; ROWS];
+    for &(row, col) in pressed {
+        state[row][col] = true;
+    }
+    return state;
+}
+
+// `ms` milliseconds after boot
+pub fn at(ms: u64) -> Instant {
+    return Instant::from_ticks(ms * 1_000);
+}
+
+pub fn key(pos: KeyPos, pressed: bool, time: Instant) -> KeyEvent {
+    return KeyEvent { pos, pressed, time };
+}
+
+// `key` resolved to `action` on the base layer
+pub fn event(pos: KeyPos, pressed: bool, action: Action, time: Instant) -> ActionEvent {
+    return ActionEvent {
+        key: key(pos, pressed, time),
+        action,
+        layer: Layer::Base,
+    };
+}
+
+fn spread(bits: u32, lines: usize) -> u32 {
+    let mask = if lines >= 32 {
+        u32::MAX
+    } else {
+        (1 << lines) - 1
+    };
+    return (bits | bits << 1 | bits >> 1) & mask;
+}
+
+impl<'a, const ROWS: usize, const COLS: usize> InputPin for SimPin<'a, ROWS, COLS> {
+    type Error = Infallible;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        return self.is_low().map(|low| !low);
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        let (rows, cols) = self.matrix.levels();
+        return Ok(match self.line {
+            Line::Row(row) => rows & (1 << row) != 0,
+            Line::Col(col) => cols & (1 << col) != 0,
+        });
+    }
+}
+
+impl<'a, const ROWS: usize, const COLS: usize> OutputPin for SimPin<'a, ROWS, COLS> {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.matrix.set_driven(self.line, true);
+        return Ok(());
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.matrix.set_driven(self.line, false);
+        return Ok(());
+    }
+}
+
+// defmt needs a logger to link once DEFMT_LOG keeps info and warn logs, the
+// tests throw them away
+#[defmt::global_logger]
+struct NullLogger;
+
+unsafe impl defmt::Logger for NullLogger {
+    fn acquire() {}
+    unsafe fn flush() {}
+    unsafe fn release() {}
+    unsafe fn write(_bytes: &[u8]) {}
+}
+
+defmt::timestamp!("{=u8}", 0);
+
+mod tests {
+    use super::*;
+    use crate::board::{WavierKeys, WavierKeysReversed};
+
+    #[test]
+    fn press_and_release() {
+        let sim = SimMatrix::<5, 15>::new(DiodeDirection::Col2Row);
+        assert_eq!(sim.scan::<WavierKeys>(), (keys(&[]), true));
+        sim.press(1, 2);
+        sim.press(4, 14);
+        assert_eq!(sim.scan::<WavierKeys>(), (keys(&[(1, 2), (4, 14)]), false));
+        sim.release(1, 2);
+        assert_eq!(sim.scan::<WavierKeys>(), (keys(&[(4, 14)]), false));
+        sim.release(4, 14);
+        assert_eq!(sim.scan::<WavierKeys>(), (keys(&[]), true));
+    }
+
+    #[test]
+    fn row2col_reads_the_same_keys() {
+        let sim = SimMatrix::<5, 15>::new(DiodeDirection::Row2Col);
+        sim.press(0, 0);
+        sim.press(3, 11);
+        let expected = (keys(&[(0, 0), (3, 11)]), false);
+        assert_eq!(sim.scan::<WavierKeysReversed>(), expected);
+    }
+
+    #[test]
+    fn diodes_keep_rectangles_apart() {
+        let sim = SimMatrix::<5, 15>::new(DiodeDirection::Col2Row);
+        let corners = [(0, 0), (0, 1), (1, 0)];
+        for (row, col) in corners {
+            sim.press(row, col);
+        }
+        assert_eq!(sim.scan::<WavierKeys>().0, keys(&corners));
+    }
+
+    #[test]
+    fn without_diodes_a_rectangle_shows_a_ghost() {
+        let mut sim = SimMatrix::<5, 15>::new(DiodeDirection::Col2Row);
+        sim.diodes = false;
+        for (row, col) in [(0, 0), (0, 1), (1, 0)] {
+            sim.press(row, col);
+        }
+        let ghosted = keys(&[(0, 0), (0, 1), (1, 0), (1, 1)]);
+        assert_eq!(sim.scan::<WavierKeys>().0, ghosted);
+
+        sim.diodes = true;
+        assert_eq!(sim.scan::<WavierKeys>().0, keys(&[(0, 0), (0, 1), (1, 0)]));
+    }
+
+    #[test]
+    fn crosstalk_spreads_to_neighbouring_inputs() {
+        let mut sim = SimMatrix::<5, 15>::new(DiodeDirection::Col2Row);
+        sim.crosstalk = true;
+        sim.press(2, 5);
+        assert_eq!(sim.scan::<WavierKeys>().0, keys(&[(2, 4), (2, 5), (2, 6)]));
+
+        sim.crosstalk = false;
+        assert_eq!(sim.scan::<WavierKeys>().0, keys(&[(2, 5)]));
+    }
+
+    #[test]
+    fn crosstalk_on_row2col_spreads_across_rows() {
+        let mut sim = SimMatrix::<5, 15>::new(DiodeDirection::Row2Col);
+        sim.crosstalk = true;
+        sim.press(0, 7);
+        assert_eq!(sim.scan::<WavierKeysReversed>().0, keys(&[(0, 7), (1, 7)]));
+    }
+}
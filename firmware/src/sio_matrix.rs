@@ -0,0 +1,146 @@
+use core::marker::PhantomData;
+
+use cortex_m::asm;
+#[cfg(feature = "calibrate-settle")]
+use cortex_m::peripheral::SYST;
+use rp_pico::hal::pac;
+
+#[cfg(feature = "calibrate-settle")]
+use crate::matrix::line_counts;
+use crate::{
+    board::{Board, DiodeDirection},
+    matrix::{ns_to_cycles, MatrixIo},
+};
+
+const FUNCSEL_SIO: u8 = 5;
+pub const FUNCSEL_PIO0: u8 = 6;
+
+// pins driven by the scanner and pins read by it, as gpio bitmasks
+fn pin_masks<B: Board<ROWS, COLS>, const ROWS: usize, const COLS: usize>() -> (u32, u32) {
+    let mut row_mask = 0;
+    for pin in B::ROW_PINS {
+        row_mask |= 1 << pin;
+    }
+    let mut col_mask = 0;
+    for pin in B::COL_PINS {
+        col_mask |= 1 << pin;
+    }
+    return match B::DIODES {
+        DiodeDirection::Col2Row => (row_mask, col_mask),
+        DiodeDirection::Row2Col => (col_mask, row_mask),
+    };
+}
+
+// hand the matrix pins over from the io bank, driven pins get `output_func`,
+// read pins are pulled up
+pub fn configure_pins<B: Board<ROWS, COLS>, const ROWS: usize, const COLS: usize>(output_func: u8) {
+    let io_bank = unsafe { &*pac::IO_BANK0::ptr() };
+    let pads = unsafe { &*pac::PADS_BANK0::ptr() };
+    let (outputs, inputs) = pin_masks::<B, ROWS, COLS>();
+    for pin in 0..30 {
+        if inputs & (1 << pin) != 0 {
+            pads.gpio[pin].modify(|_, w| w.pue().set_bit().pde().clear_bit().ie().set_bit());
+            io_bank.gpio[pin]
+                .gpio_ctrl
+                .write(|w| unsafe { w.funcsel().bits(FUNCSEL_SIO) });
+        }
+        if outputs & (1 << pin) != 0 {
+            pads.gpio[pin].modify(|_, w| w.od().clear_bit().ie().set_bit());
+            io_bank.gpio[pin]
+                .gpio_ctrl
+                .write(|w| unsafe { w.funcsel().bits(output_func) });
+        }
+    }
+}
+
+// matrix wired straight to the SIO, one gpio_in read covers every input line
+pub struct SioMatrix<B: Board<ROWS, COLS>, const ROWS: usize, const COLS: usize> {
+    input_mask: u32,
+    settle_cycles: u32,
+    _board: PhantomData<B>,
+}
+
+impl<B: Board<ROWS, COLS>, const ROWS: usize, const COLS: usize> SioMatrix<B, ROWS, COLS> {
+    // call after `rp_pico::Pins::new`, which resets the io bank
+    pub fn new(sys_hz: u32) -> Self {
+        assert!(ROWS <= 32 && COLS <= 32);
+        configure_pins::<B, ROWS, COLS>(FUNCSEL_SIO);
+        let sio = unsafe { &*pac::SIO::ptr() };
+        let (output_mask, input_mask) = pin_masks::<B, ROWS, COLS>();
+        sio.gpio_out_set.write(|w| unsafe { w.bits(output_mask) });
+        sio.gpio_oe_set.write(|w| unsafe { w.bits(output_mask) });
+        SioMatrix {
+            input_mask,
+            settle_cycles: ns_to_cycles(B::SETTLE_NS, sys_hz),
+            _board: PhantomData,
+        }
+    }
+
+    // drive every line with a generous settle time and measure how long each
+    // closed input takes to read high again once its line is released. returns
+    // the slowest recovery in core cycles, None while no key is held down.
+    // SYST has to count core cycles from its full 24 bit reload.
+    #[cfg(feature = "calibrate-settle")]
+    pub fn calibrate(&mut self) -> Option<u32> {
+        let sio = unsafe { &*pac::SIO::ptr() };
+        let mut worst: Option<u32> = None;
+        for output in 0..line_counts::<B, ROWS, COLS>().0 {
+            self.drive(output, true);
+            asm::delay(self.settle_cycles * 4 + 100);
+            let pressed = !sio.gpio_in.read().bits() & self.input_mask;
+            let start = SYST::get_current();
+            self.drive(output, false);
+            if pressed == 0 {
+                continue;
+            }
+            let mut timeout = 100_000;
+            while !sio.gpio_in.read().bits() & pressed != 0 && timeout > 0 {
+                timeout -= 1;
+            }
+            let elapsed = start.wrapping_sub(SYST::get_current()) & 0x00ff_ffff;
+            worst = Some(worst.unwrap_or(0).max(elapsed));
+        }
+        return worst;
+    }
+}
+
+impl<B: Board<ROWS, COLS>, const ROWS: usize, const COLS: usize> MatrixIo
+    for SioMatrix<B, ROWS, COLS>
+{
+    fn drive(&mut self, index: usize, active: bool) {
+        let sio = unsafe { &*pac::SIO::ptr() };
+        let pin = match B::DIODES {
+            DiodeDirection::Col2Row => B::ROW_PINS[index],
+            DiodeDirection::Row2Col => B::COL_PINS[index],
+        };
+        if active {
+            sio.gpio_out_clr.write(|w| unsafe { w.bits(1 << pin) });
+        } else {
+            sio.gpio_out_set.write(|w| unsafe { w.bits(1 << pin) });
+        }
+    }
+
+    fn read(&self) -> u32 {
+        let sio = unsafe { &*pac::SIO::ptr() };
+        let pressed = !sio.gpio_in.read().bits() & self.input_mask;
+        return match B::DIODES {
+            DiodeDirection::Col2Row => line_bits(pressed, &B::COL_PINS),
+            DiodeDirection::Row2Col => line_bits(pressed, &B::ROW_PINS),
+        };
+    }
+
+    fn settle(&self) {
+        asm::delay(self.settle_cycles);
+    }
+}
+
+// gpio bitmask to input line bitmask
+fn line_bits(gpio: u32, pins: &[u8]) -> u32 {
+    let mut bits = 0;
+    for (line, &pin) in pins.iter().enumerate() {
+        if gpio & (1 << pin) != 0 {
+            bits |= 1 << line;
+        }
+    }
+    return bits;
+}
@@ -0,0 +1,56 @@
+// the six keycode slots of the boot keyboard report. a keycode keeps its slot
+// until it is no longer sent, new keycodes take free slots in the order they
+// show up and wait for one while all six are taken.
+pub struct KeySlots {
+    codes: [u8; 6],
+}
+
+impl KeySlots {
+    pub fn new() -> Self {
+        KeySlots { codes: [0; 6] }
+    }
+
+    // `codes` is everything that should be sent this report, keycodes without
+    // a slot yet are placed in the order given
+    pub fn assign(&mut self, codes: &[u8]) -> [u8; 6] {
+        for slot in self.codes.iter_mut() {
+            if !codes.contains(slot) {
+                *slot = 0;
+            }
+        }
+        for &code in codes {
+            if self.codes.contains(&code) {
+                continue;
+            }
+            match self.codes.iter_mut().find(|slot| **slot == 0) {
+                Some(slot) => *slot = code,
+                None => break,
+            }
+        }
+        return self.codes;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keycodes_keep_their_slots() {
+        let mut slots = KeySlots::new();
+        assert_eq!(slots.assign(&[9, 4]), [9, 4, 0, 0, 0, 0]);
+        assert_eq!(slots.assign(&[4, 9, 7]), [9, 4, 7, 0, 0, 0]);
+        assert_eq!(slots.assign(&[4, 7]), [0, 4, 7, 0, 0, 0]);
+        assert_eq!(slots.assign(&[4, 7, 5]), [5, 4, 7, 0, 0, 0]);
+    }
+
+    #[test]
+    fn seventh_keycode_waits_for_a_free_slot() {
+        let mut slots = KeySlots::new();
+        let held = [4, 5, 6, 7, 8, 9, 10];
+        assert_eq!(slots.assign(&held), [4, 5, 6, 7, 8, 9]);
+        assert_eq!(slots.assign(&held), [4, 5, 6, 7, 8, 9]);
+        // 6 is released, 10 takes its slot
+        assert_eq!(slots.assign(&[4, 5, 7, 8, 9, 10]), [4, 5, 10, 7, 8, 9]);
+    }
+}
@@ -0,0 +1,166 @@
+use crate::{
+    action::Action,
+    clock::{Duration, Instant},
+    keycodes::KeyCodes,
+    pipeline::{ActionEvent, ActionStage, DeferredRelease, KeyEvent},
+};
+
+const TAPPING_TERM: Duration = Duration::millis(200); // longest press still sending the tap
+
+// SpaceCadet keys hold their modifiers right away and send their tap keycode
+// when released before TAPPING_TERM without another key going down in
+// between. to the later stages they look like plain modifier keys.
+pub struct SpaceCadet {
+    // the SpaceCadet key held down, until another key interrupts it
+    tapping: Option<ActionEvent>,
+    release: DeferredRelease,
+}
+
+impl SpaceCadet {
+    pub fn new() -> Self {
+        SpaceCadet {
+            tapping: None,
+            release: DeferredRelease::new(),
+        }
+    }
+}
+
+impl ActionStage for SpaceCadet {
+    fn process(&mut self, event: ActionEvent, emit: &mut impl FnMut(ActionEvent)) {
+        self.release.flush(event.key.time, emit);
+        let Action::SpaceCadet(hold, tap_mods, tap) = event.action else {
+            if event.key.pressed {
+                self.tapping = None;
+            }
+            emit(event);
+            return;
+        };
+
+        // to the rest of the pipeline the key is a plain modifier key
+        let held = ActionEvent {
+            action: Action::ModKey(hold, KeyCodes::Reserved),
+            ..event
+        };
+        emit(held);
+        if event.key.pressed {
+            self.tapping = Some(event);
+            return;
+        }
+        let Some(pressed) = self.tapping.take() else {
+            return;
+        };
+        if pressed.key.pos == event.key.pos && event.key.time - pressed.key.time < TAPPING_TERM {
+            let tap = Action::ModKey(tap_mods, tap);
+            emit(ActionEvent {
+                key: KeyEvent {
+                    pressed: true,
+                    ..event.key
+                },
+                action: tap,
+                layer: event.layer,
+            });
+            self.release.defer(
+                ActionEvent {
+                    action: tap,
+                    ..event
+                },
+                emit,
+            );
+        }
+    }
+
+    fn tick(&mut self, now: Instant, emit: &mut impl FnMut(ActionEvent)) {
+        self.release.flush(now, emit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        action::{LSFT, SC_LSPO},
+        clock::{Clock, FakeClock},
+        keycodes::KeyCodes::*,
+        layout::pos,
+        sim::event,
+    };
+
+    const SHIFT_KEY: Action = Action::ModKey(0x02, Reserved);
+
+    // what reached the next stage, in order
+    fn actions(events: &[ActionEvent]) -> Vec<(Action, bool)> {
+        return events
+            .iter()
+            .map(|event| (event.action, event.key.pressed))
+            .collect();
+    }
+
+    #[test]
+    fn tap_sends_the_paren() {
+        let clock = FakeClock::new();
+        let mut space_cadet = SpaceCadet::new();
+        let mut events = Vec::new();
+        space_cadet.process(event(pos(3, 0), true, SC_LSPO, clock.now()), &mut |e| {
+            events.push(e)
+        });
+        clock.advance(TAPPING_TERM - Duration::millis(1));
+        space_cadet.process(event(pos(3, 0), false, SC_LSPO, clock.now()), &mut |e| {
+            events.push(e)
+        });
+        space_cadet.tick(clock.now(), &mut |e| events.push(e));
+        // the paren's release waits for the next scan
+        assert_eq!(events.len(), 3);
+        clock.advance(Duration::millis(1));
+        space_cadet.tick(clock.now(), &mut |e| events.push(e));
+        let expected = [
+            (SHIFT_KEY, true),
+            (SHIFT_KEY, false),
+            (LSFT(Key9), true),
+            (LSFT(Key9), false),
+        ];
+        assert_eq!(actions(&events), expected);
+    }
+
+    #[test]
+    fn hold_past_the_tapping_term_is_only_shift() {
+        let clock = FakeClock::new();
+        let mut space_cadet = SpaceCadet::new();
+        let mut events = Vec::new();
+        space_cadet.process(event(pos(3, 0), true, SC_LSPO, clock.now()), &mut |e| {
+            events.push(e)
+        });
+        clock.advance(TAPPING_TERM);
+        space_cadet.process(event(pos(3, 0), false, SC_LSPO, clock.now()), &mut |e| {
+            events.push(e)
+        });
+        clock.advance(Duration::millis(1));
+        space_cadet.tick(clock.now(), &mut |e| events.push(e));
+        assert_eq!(actions(&events), [(SHIFT_KEY, true), (SHIFT_KEY, false)]);
+    }
+
+    #[test]
+    fn key_pressed_in_between_makes_it_shift() {
+        let clock = FakeClock::new();
+        let mut space_cadet = SpaceCadet::new();
+        let mut events = Vec::new();
+        let a = Action::Key(KeyA);
+        space_cadet.process(event(pos(3, 0), true, SC_LSPO, clock.now()), &mut |e| {
+            events.push(e)
+        });
+        clock.advance(Duration::millis(20));
+        space_cadet.process(event(pos(3, 1), true, a, clock.now()), &mut |e| {
+            events.push(e)
+        });
+        space_cadet.process(event(pos(3, 1), false, a, clock.now()), &mut |e| {
+            events.push(e)
+        });
+        clock.advance(Duration::millis(20));
+        space_cadet.process(event(pos(3, 0), false, SC_LSPO, clock.now()), &mut |e| {
+            events.push(e)
+        });
+        clock.advance(Duration::millis(1));
+        space_cadet.tick(clock.now(), &mut |e| events.push(e));
+        let expected = [(SHIFT_KEY, true), (a, true), (a, false), (SHIFT_KEY, false)];
+        assert_eq!(actions(&events), expected);
+    }
+}